@@ -59,6 +59,32 @@ fn start_android_tasks(app: &App) -> Vec<JoinHandle<()>> {
             f_rx: app.f_cam_rx.activate_cloned(),
             raw_eyes_rx: app.raw_eyes_rx.activate_cloned(),
             combined_eyes_rx: app.combined_eyes_rx.activate_cloned(),
+            #[cfg(feature = "inference")]
+            inference_control: app.inference_control.clone(),
+            #[cfg(feature = "inference")]
+            model_input_rx: app.model_input_rx.activate_cloned(),
+            #[cfg(feature = "inference")]
+            filter_control: app.filter_control.clone(),
+            #[cfg(feature = "inference")]
+            calibration_control: app.calibration_control.clone(),
+            #[cfg(feature = "inference")]
+            recenter_control: app.recenter_control.clone(),
+            #[cfg(feature = "inference")]
+            blink_rx: app.blink_rx.activate_cloned(),
+            #[cfg(feature = "inference")]
+            movement_control: app.movement_control.clone(),
+            #[cfg(feature = "inference")]
+            movement_rx: app.movement_rx.activate_cloned(),
+            #[cfg(feature = "inference")]
+            vergence_control: app.vergence_control.clone(),
+            #[cfg(feature = "inference")]
+            tracking_control: app.tracking_control.clone(),
+            #[cfg(feature = "inference")]
+            post_process_control: app.post_process_control.clone(),
+            #[cfg(feature = "inference")]
+            output_profile_control: app.output_profile_control.clone(),
+            #[cfg(feature = "inference")]
+            osc_control: app.osc_control.clone(),
         }));
     }
 
@@ -66,15 +92,15 @@ fn start_android_tasks(app: &App) -> Vec<JoinHandle<()>> {
 
     #[cfg(feature = "inference")]
     {
-        use crate::data_processing::process_gaze;
+        use crate::data_processing::{GazeControls, process_gaze};
+        use crate::eye_movements::classify_movements;
         use crate::inference::eye_inference;
 
-        const THREADS_PER_EYE: usize = 1;
-
         tasks.push(eye_inference(
             app.eyes_cam_rx.activate_cloned(),
             app.raw_eyes_tx.clone(),
-            THREADS_PER_EYE,
+            app.model_input_tx.clone(),
+            app.inference_control.clone(),
         ));
 
         // Filter
@@ -82,12 +108,22 @@ fn start_android_tasks(app: &App) -> Vec<JoinHandle<()>> {
         tasks.push(process_gaze(
             app.raw_eyes_rx.activate_cloned(),
             app.combined_eyes_tx.clone(),
+            app.blink_tx.clone(),
+            GazeControls::new(app),
+        ));
+
+        // Eye movements
+
+        tasks.push(classify_movements(
+            app.combined_eyes_rx.activate_cloned(),
+            app.movement_tx.clone(),
+            app.movement_control.clone(),
         ));
     }
 
     // OpenXR output
 
-    start_openxr_output(&app.combined_eyes_rx);
+    start_openxr_output(&app.combined_eyes_rx, app.output_profile_control.clone());
 
     tasks
 }
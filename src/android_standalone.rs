@@ -6,13 +6,18 @@ use crate::camera_dispatcher::{
 };
 use crate::camera_manager;
 use crate::camera_server::start_camera_server;
+use crate::control_server::{ControlContext, start_control_server};
 use crate::frame_server::start_frame_server;
 
 #[cfg(feature = "inference")]
-use crate::data_processing::process_gaze;
+use crate::data_processing::{GazeControls, process_gaze};
+#[cfg(feature = "inference")]
+use crate::eye_movements::classify_movements;
 #[cfg(feature = "inference")]
 use crate::inference::eye_inference;
 #[cfg(feature = "inference")]
+use crate::osc_receiver::start_osc_receiver;
+#[cfg(feature = "inference")]
 use crate::osc_sender::start_osc_sender;
 
 use crate::structs::Eye;
@@ -56,6 +61,32 @@ fn android_main(android_app: AndroidApp) {
             f_rx: app.f_cam_rx.activate_cloned(),
             raw_eyes_rx: app.raw_eyes_rx.activate_cloned(),
             combined_eyes_rx: app.combined_eyes_rx.activate_cloned(),
+            #[cfg(feature = "inference")]
+            inference_control: app.inference_control.clone(),
+            #[cfg(feature = "inference")]
+            model_input_rx: app.model_input_rx.activate_cloned(),
+            #[cfg(feature = "inference")]
+            filter_control: app.filter_control.clone(),
+            #[cfg(feature = "inference")]
+            calibration_control: app.calibration_control.clone(),
+            #[cfg(feature = "inference")]
+            recenter_control: app.recenter_control.clone(),
+            #[cfg(feature = "inference")]
+            blink_rx: app.blink_rx.activate_cloned(),
+            #[cfg(feature = "inference")]
+            movement_control: app.movement_control.clone(),
+            #[cfg(feature = "inference")]
+            movement_rx: app.movement_rx.activate_cloned(),
+            #[cfg(feature = "inference")]
+            vergence_control: app.vergence_control.clone(),
+            #[cfg(feature = "inference")]
+            tracking_control: app.tracking_control.clone(),
+            #[cfg(feature = "inference")]
+            post_process_control: app.post_process_control.clone(),
+            #[cfg(feature = "inference")]
+            output_profile_control: app.output_profile_control.clone(),
+            #[cfg(feature = "inference")]
+            osc_control: app.osc_control.clone(),
         },
     )
 }
@@ -69,6 +100,8 @@ fn start_android_standalone_tasks(app: &App) -> Vec<JoinHandle<()>> {
         app.f_cam_rx.clone(),
     ));
 
+    tasks.push(start_control_server(ControlContext::new(app), false));
+
     tasks.push(start_serial_watcher(std::collections::HashMap::from([
         (
             "30:30:F9:33:DD:7C".to_string(),
@@ -89,12 +122,11 @@ fn start_android_standalone_tasks(app: &App) -> Vec<JoinHandle<()>> {
 
     #[cfg(feature = "inference")]
     {
-        const THREADS_PER_EYE: usize = 1;
-
         tasks.push(eye_inference(
             app.eyes_cam_rx.activate_cloned(),
             app.raw_eyes_tx.clone(),
-            THREADS_PER_EYE,
+            app.model_input_tx.clone(),
+            app.inference_control.clone(),
         ));
 
         // Filter
@@ -102,13 +134,31 @@ fn start_android_standalone_tasks(app: &App) -> Vec<JoinHandle<()>> {
         tasks.push(process_gaze(
             app.raw_eyes_rx.activate_cloned(),
             app.combined_eyes_tx.clone(),
+            app.blink_tx.clone(),
+            GazeControls::new(app),
+        ));
+
+        // Eye movements
+
+        tasks.push(classify_movements(
+            app.combined_eyes_rx.activate_cloned(),
+            app.movement_tx.clone(),
+            app.movement_control.clone(),
         ));
 
         // OSC sender
 
         tasks.push(start_osc_sender(
             app.combined_eyes_rx.activate_cloned(),
-            "localhost:9000".to_string(),
+            app.osc_control.clone(),
+            app.output_profile_control.clone(),
+        ));
+
+        // OSC receiver
+
+        tasks.push(start_osc_receiver(
+            "127.0.0.1:9001".to_string(),
+            app.recenter_control.clone(),
         ));
     }
 
@@ -1,7 +1,31 @@
+use std::sync::Arc;
+
 use async_broadcast::{InactiveReceiver, Sender};
 
+#[cfg(feature = "inference")]
+use crate::blink::BlinkEvent;
+#[cfg(feature = "inference")]
+use crate::calibration::CalibrationControl;
 use crate::camera::Frame;
+#[cfg(feature = "inference")]
+use crate::eye_movements::{MovementControl, MovementEvent};
+#[cfg(feature = "inference")]
+use crate::filters::FilterControl;
+#[cfg(feature = "inference")]
+use crate::inference::{InferenceControl, ModelInputFrame};
+#[cfg(feature = "inference")]
+use crate::osc_sender::OscControl;
+#[cfg(any(feature = "inference", feature = "openxr-api-layer"))]
+use crate::output_profiles::OutputProfileControl;
+#[cfg(feature = "inference")]
+use crate::post_processing::PostProcessControl;
+#[cfg(feature = "inference")]
+use crate::recenter::RecenterControl;
 use crate::structs::{CombinedEyeGazeState, EyesFrame, EyesGazeState};
+#[cfg(feature = "inference")]
+use crate::tracking::TrackingControl;
+#[cfg(feature = "inference")]
+use crate::vergence::VergenceControl;
 
 // Utility for creating a broadcast pair with 1 element queue, overflow on, and deactivated receiver.
 pub fn inactive_broadcast<T>() -> (Sender<T>, InactiveReceiver<T>) {
@@ -10,6 +34,13 @@ pub fn inactive_broadcast<T>() -> (Sender<T>, InactiveReceiver<T>) {
     (tx, rx.deactivate())
 }
 
+// Like `inactive_broadcast`, but for events that shouldn't replace each other, so they're queued.
+pub fn inactive_event_broadcast<T>() -> (Sender<T>, InactiveReceiver<T>) {
+    let (tx, mut rx) = async_broadcast::broadcast::<T>(16);
+    rx.set_overflow(true);
+    (tx, rx.deactivate())
+}
+
 // Contains all the elements and senders/receivers.
 pub(crate) struct App {
     // Eye tracking camera(s).
@@ -23,10 +54,44 @@ pub(crate) struct App {
     // Inference.
     pub raw_eyes_tx: Sender<EyesGazeState>,
     pub raw_eyes_rx: InactiveReceiver<EyesGazeState>,
+    #[cfg(feature = "inference")]
+    pub inference_control: Arc<InferenceControl>,
+    #[cfg(feature = "inference")]
+    pub model_input_tx: Sender<ModelInputFrame>,
+    #[cfg(feature = "inference")]
+    pub model_input_rx: InactiveReceiver<ModelInputFrame>,
 
     // Combined gaze.
+    #[cfg(feature = "inference")]
+    pub filter_control: Arc<FilterControl>,
+    #[cfg(feature = "inference")]
+    pub calibration_control: Arc<CalibrationControl>,
+    #[cfg(feature = "inference")]
+    pub recenter_control: Arc<RecenterControl>,
+    #[cfg(feature = "inference")]
+    pub vergence_control: Arc<VergenceControl>,
+    #[cfg(feature = "inference")]
+    pub tracking_control: Arc<TrackingControl>,
+    #[cfg(feature = "inference")]
+    pub post_process_control: Arc<PostProcessControl>,
     pub combined_eyes_tx: Sender<CombinedEyeGazeState>,
     pub combined_eyes_rx: InactiveReceiver<CombinedEyeGazeState>,
+    #[cfg(any(feature = "inference", feature = "openxr-api-layer"))]
+    pub output_profile_control: Arc<OutputProfileControl>,
+    #[cfg(feature = "inference")]
+    pub osc_control: Arc<OscControl>,
+    #[cfg(feature = "inference")]
+    pub blink_tx: Sender<BlinkEvent>,
+    #[cfg(feature = "inference")]
+    pub blink_rx: InactiveReceiver<BlinkEvent>,
+
+    // Eye movement classification.
+    #[cfg(feature = "inference")]
+    pub movement_control: Arc<MovementControl>,
+    #[cfg(feature = "inference")]
+    pub movement_tx: Sender<MovementEvent>,
+    #[cfg(feature = "inference")]
+    pub movement_rx: InactiveReceiver<MovementEvent>,
 }
 
 impl App {
@@ -42,11 +107,26 @@ impl App {
         // Inference channels
 
         let (raw_eyes_tx, raw_eyes_rx) = inactive_broadcast::<EyesGazeState>();
+        #[cfg(feature = "inference")]
+        let (model_input_tx, model_input_rx) = inactive_broadcast::<ModelInputFrame>();
 
         // Gaze processing channels
 
         let (combined_eyes_tx, combined_eyes_rx) = inactive_broadcast::<CombinedEyeGazeState>();
 
+        #[cfg(feature = "inference")]
+        let (blink_tx, blink_rx) = inactive_event_broadcast::<BlinkEvent>();
+
+        // Eye movement channels
+
+        #[cfg(feature = "inference")]
+        let (movement_tx, movement_rx) = inactive_event_broadcast::<MovementEvent>();
+
+        // Calibration drops the recenter offsets it invalidates
+
+        #[cfg(feature = "inference")]
+        let recenter_control = Arc::new(RecenterControl::default());
+
         App {
             eye_cam_tx,
             eyes_cam_rx: eye_cam_rx,
@@ -56,9 +136,42 @@ impl App {
 
             raw_eyes_tx,
             raw_eyes_rx,
-
+            #[cfg(feature = "inference")]
+            inference_control: Default::default(),
+            #[cfg(feature = "inference")]
+            model_input_tx,
+            #[cfg(feature = "inference")]
+            model_input_rx,
+
+            #[cfg(feature = "inference")]
+            filter_control: Default::default(),
+            #[cfg(feature = "inference")]
+            calibration_control: Arc::new(CalibrationControl::load(recenter_control.clone())),
+            #[cfg(feature = "inference")]
+            recenter_control,
+            #[cfg(feature = "inference")]
+            vergence_control: Default::default(),
+            #[cfg(feature = "inference")]
+            tracking_control: Default::default(),
+            #[cfg(feature = "inference")]
+            post_process_control: Default::default(),
             combined_eyes_tx,
             combined_eyes_rx,
+            #[cfg(any(feature = "inference", feature = "openxr-api-layer"))]
+            output_profile_control: Default::default(),
+            #[cfg(feature = "inference")]
+            osc_control: Default::default(),
+            #[cfg(feature = "inference")]
+            blink_tx,
+            #[cfg(feature = "inference")]
+            blink_rx,
+
+            #[cfg(feature = "inference")]
+            movement_control: Default::default(),
+            #[cfg(feature = "inference")]
+            movement_tx,
+            #[cfg(feature = "inference")]
+            movement_rx,
         }
     }
 }
@@ -0,0 +1,271 @@
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use image::{GenericImageView, RgbImage};
+use ort::session::builder::GraphOptimizationLevel;
+
+use crate::dataset::{decode_eye_image, load_eye_images};
+use crate::inference::{
+    EyeHistory, ModelLayout, crop_eye, model_input_image, new_session_builder, resize_eye,
+    run_model,
+};
+use crate::preprocessing::PreprocessingSettings;
+use crate::segmentation::SegmentationMapping;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum OptimizationLevel {
+    Disable,
+    Level1,
+    Level2,
+    Level3,
+}
+
+impl From<OptimizationLevel> for GraphOptimizationLevel {
+    fn from(level: OptimizationLevel) -> Self {
+        match level {
+            OptimizationLevel::Disable => GraphOptimizationLevel::Disable,
+            OptimizationLevel::Level1 => GraphOptimizationLevel::Level1,
+            OptimizationLevel::Level2 => GraphOptimizationLevel::Level2,
+            OptimizationLevel::Level3 => GraphOptimizationLevel::Level3,
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct BenchmarkArgs {
+    /// Directory with recorded eye images, e.g. frame server captures
+    #[arg(default_value = "./images")]
+    images_dir: PathBuf,
+
+    /// Path to the ONNX model
+    #[arg(short = 'm', default_value = "./model.onnx")]
+    model_path: PathBuf,
+
+    /// Comma separated thread counts per eye to benchmark
+    #[arg(short = 't', value_delimiter = ',', default_value = "1,2,4")]
+    threads: Vec<usize>,
+
+    /// Comma separated graph optimization levels to benchmark
+    #[arg(short = 'O', value_delimiter = ',', default_value = "level3")]
+    optimization_levels: Vec<OptimizationLevel>,
+
+    /// Maximum number of images to load
+    #[arg(short = 'n', default_value_t = 1000)]
+    limit: usize,
+
+    /// Number of passes over the images per configuration
+    #[arg(short = 'p', default_value_t = 3)]
+    passes: usize,
+
+    /// Number of untimed runs before measuring
+    #[arg(short = 'w', default_value_t = 10)]
+    warmup: usize,
+}
+
+struct EyeImage {
+    image: RgbImage,
+    is_left: bool,
+}
+
+#[derive(Default)]
+struct StageTimes(Vec<Duration>);
+
+impl StageTimes {
+    fn push(&mut self, duration: Duration) {
+        self.0.push(duration);
+    }
+
+    fn mean_ms(&self) -> f64 {
+        if self.0.is_empty() {
+            return 0.0;
+        }
+        self.0.iter().sum::<Duration>().as_secs_f64() * 1000.0 / self.0.len() as f64
+    }
+
+    fn p95_ms(&mut self) -> f64 {
+        if self.0.is_empty() {
+            return 0.0;
+        }
+        self.0.sort();
+        let index = ((self.0.len() - 1) as f64 * 0.95).round() as usize;
+        self.0[index].as_secs_f64() * 1000.0
+    }
+}
+
+/// Memory value of the whole process, only available on Linux/Android.
+fn status_kib(key: &str) -> Option<u64> {
+    let status = std::fs::read_to_string("/proc/self/status").ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix(key))
+        .and_then(|value| value.trim().trim_end_matches("kB").trim().parse().ok())
+}
+
+/// Lowers the peak resident memory to the current one and returns it, so the next peak
+/// belongs to a single configuration. Needs Linux 4.0 or newer.
+fn reset_peak_memory_kib() -> Option<u64> {
+    std::fs::write("/proc/self/clear_refs", "5").ok()?;
+    status_kib("VmRSS:")
+}
+
+pub fn run_benchmark(args: BenchmarkArgs) {
+    let images = match load_eye_images(&args.images_dir, args.limit) {
+        Ok(paths) => paths
+            .into_iter()
+            .filter_map(|(path, is_left)| match decode_eye_image(&path) {
+                Ok(image) => Some(EyeImage { image, is_left }),
+                Err(err) => {
+                    eprintln!("Skipping {}: {err}", path.display());
+                    None
+                }
+            })
+            .collect::<Vec<_>>(),
+        Err(err) => {
+            eprintln!("Failed to read {}: {err}", args.images_dir.display());
+            std::process::exit(1);
+        }
+    };
+
+    if images.is_empty() {
+        eprintln!("No images found in {}", args.images_dir.display());
+        std::process::exit(1);
+    }
+
+    println!(
+        "Benchmarking {} on {} images, {} pass(es) each",
+        args.model_path.display(),
+        images.len(),
+        args.passes
+    );
+    println!(
+        "{:<8} {:>7} | {:>19} {:>19} {:>19} {:>19} | {:>9} | {:>10}",
+        "opt", "threads", "crop ms", "resize ms", "tensor ms", "run ms", "eyes/s", "peak +KiB"
+    );
+
+    for &optimization_level in &args.optimization_levels {
+        for &threads in &args.threads {
+            let baseline_kib = reset_peak_memory_kib();
+
+            let session = new_session_builder(threads, optimization_level.into())
+                .and_then(|builder| builder.commit_from_file(&args.model_path));
+            let mut model = match session {
+                Ok(model) => model,
+                Err(err) => {
+                    eprintln!("{optimization_level:?} with {threads} thread(s): {err}");
+                    continue;
+                }
+            };
+
+            let layout = ModelLayout::from_session(&model);
+            let mut l_history = EyeHistory::default();
+            let mut r_history = EyeHistory::default();
+            let preprocessing = PreprocessingSettings::default();
+            let mapping = SegmentationMapping::default();
+
+            let mut crop = StageTimes::default();
+            let mut resize = StageTimes::default();
+            let mut tensor = StageTimes::default();
+            let mut run = StageTimes::default();
+
+            for eye in images.iter().cycle().take(args.warmup) {
+                let history = if eye.is_left {
+                    &mut l_history
+                } else {
+                    &mut r_history
+                };
+                let (image, estimates) = model_input_image(
+                    &resize_eye(&crop_eye(
+                        eye.image.view(0, 0, eye.image.width(), eye.image.height()),
+                        eye.is_left,
+                    )),
+                    &preprocessing,
+                );
+                history.reset();
+                let input = history.input_tensor(&layout, image);
+                let _ = run_model(
+                    &mut model,
+                    &layout,
+                    history,
+                    &mapping,
+                    &input,
+                    estimates,
+                    eye.is_left,
+                );
+            }
+
+            let start = Instant::now();
+            let mut failures = 0;
+
+            for _ in 0..args.passes {
+                for eye in &images {
+                    let history = if eye.is_left {
+                        &mut l_history
+                    } else {
+                        &mut r_history
+                    };
+
+                    let t = Instant::now();
+                    let cropped = crop_eye(
+                        eye.image.view(0, 0, eye.image.width(), eye.image.height()),
+                        eye.is_left,
+                    );
+                    crop.push(t.elapsed());
+
+                    let t = Instant::now();
+                    let resized = resize_eye(&cropped);
+                    resize.push(t.elapsed());
+
+                    let t = Instant::now();
+                    let (image, estimates) = model_input_image(&resized, &preprocessing);
+                    // The images are unrelated, each one starts a fresh history.
+                    history.reset();
+                    let input = history.input_tensor(&layout, image);
+                    tensor.push(t.elapsed());
+
+                    let t = Instant::now();
+                    if run_model(
+                        &mut model,
+                        &layout,
+                        history,
+                        &mapping,
+                        &input,
+                        estimates,
+                        eye.is_left,
+                    )
+                    .is_err()
+                    {
+                        failures += 1;
+                    }
+                    run.push(t.elapsed());
+                }
+            }
+
+            let total = start.elapsed();
+            let throughput = (images.len() * args.passes) as f64 / total.as_secs_f64();
+
+            let stage = |times: &mut StageTimes| {
+                format!("{:6.3} (p95 {:6.3})", times.mean_ms(), times.p95_ms())
+            };
+
+            println!(
+                "{:<8} {:>7} | {} {} {} {} | {:>9.1} | {:>10}",
+                format!("{optimization_level:?}"),
+                threads,
+                stage(&mut crop),
+                stage(&mut resize),
+                stage(&mut tensor),
+                stage(&mut run),
+                throughput,
+                // Without a reset the peak could be from an earlier configuration.
+                baseline_kib
+                    .zip(status_kib("VmHWM:"))
+                    .map(|(baseline, peak)| peak.saturating_sub(baseline).to_string())
+                    .unwrap_or("n/a".to_string()),
+            );
+
+            if failures > 0 {
+                eprintln!("  {failures} run(s) failed");
+            }
+        }
+    }
+}
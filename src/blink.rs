@@ -0,0 +1,115 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::calibration::EyelidCalibration;
+use crate::structs::{Eye, EyeGazeState, Timestamp};
+
+/// Closed amount above which the eye counts as closing.
+const CLOSE_THRESHOLD: f32 = 0.7;
+/// Closed amount below which it counts as open again. The gap avoids flickering.
+const OPEN_THRESHOLD: f32 = 0.4;
+/// Shorter closures are noise, the gaze is still held but no events are sent.
+const MIN_BLINK_DURATION: Duration = Duration::from_millis(50);
+/// Blink rate is measured over this window.
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug)]
+pub enum BlinkEvent {
+    /// Sent once the closure lasted `MIN_BLINK_DURATION`, with the time it started.
+    Start { eye: Eye, timestamp: Timestamp },
+    End {
+        eye: Eye,
+        timestamp: Timestamp,
+        duration: Duration,
+    },
+}
+
+/// Detects blinks of one eye and holds its gaze while the eyelid is down, when the
+/// model output is meaningless.
+#[derive(Debug)]
+pub struct BlinkDetector {
+    eye: Eye,
+    closed_since: Option<Timestamp>,
+    confirmed: bool,
+    // Gaze of the last sample with the eye open.
+    held: Option<(f32, f32)>,
+    blinks: VecDeque<Timestamp>,
+}
+
+impl BlinkDetector {
+    pub fn new(eye: Eye) -> Self {
+        Self {
+            eye,
+            closed_since: None,
+            confirmed: false,
+            held: None,
+            blinks: VecDeque::new(),
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        state: EyeGazeState,
+        timestamp: Timestamp,
+        calibration: &EyelidCalibration,
+        events: &mut Vec<BlinkEvent>,
+    ) -> EyeGazeState {
+        // Invalid samples still carry the eyelid, a closing eye often loses its gaze.
+        let closed = calibration.closed_amount(state.eyelid);
+
+        match self.closed_since {
+            None if closed > CLOSE_THRESHOLD => self.closed_since = Some(timestamp),
+            None => {
+                // Partially closed eyes keep the gaze, but it isn't trusted to be held.
+                if closed < OPEN_THRESHOLD && state.valid {
+                    self.held = Some((state.pitch, state.yaw));
+                }
+            }
+            Some(start) => {
+                let duration = timestamp.duration_since(start).unwrap_or_default();
+                if closed < OPEN_THRESHOLD {
+                    if self.confirmed {
+                        events.push(BlinkEvent::End {
+                            eye: self.eye,
+                            timestamp,
+                            duration,
+                        });
+                        self.blinks.push_back(start);
+                    }
+                    self.closed_since = None;
+                    self.confirmed = false;
+                    if state.valid {
+                        self.held = Some((state.pitch, state.yaw));
+                    }
+                } else if !self.confirmed && duration >= MIN_BLINK_DURATION {
+                    self.confirmed = true;
+                    events.push(BlinkEvent::Start {
+                        eye: self.eye,
+                        timestamp: start,
+                    });
+                }
+            }
+        }
+
+        while let Some(&oldest) = self.blinks.front() {
+            if timestamp.duration_since(oldest).unwrap_or_default() <= RATE_WINDOW {
+                break;
+            }
+            self.blinks.pop_front();
+        }
+
+        match (self.closed_since, self.held) {
+            (Some(_), Some((pitch, yaw))) => EyeGazeState {
+                pitch,
+                yaw,
+                ..state
+            },
+            _ => state,
+        }
+    }
+
+    /// Blinks per minute over the last `RATE_WINDOW`.
+    pub fn rate(&self) -> f32 {
+        self.blinks.len() as f32 * 60.0 / RATE_WINDOW.as_secs_f32()
+    }
+}
@@ -0,0 +1,496 @@
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use serde_json::json;
+
+use crate::filters::median;
+use crate::recenter::RecenterControl;
+use crate::structs::{EYELID_NEUTRAL_VALUE, Eye, EyeGazeState};
+
+/// Point the user looks at, in degrees relative to straight ahead.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CalibrationTarget {
+    pub pitch: f32,
+    pub yaw: f32,
+    /// Validation targets aren't used for fitting, only to measure the error.
+    pub validation: bool,
+}
+
+const fn target(pitch: f32, yaw: f32, validation: bool) -> CalibrationTarget {
+    CalibrationTarget {
+        pitch,
+        yaw,
+        validation,
+    }
+}
+
+/// A 3x3 grid to fit on, then 4 points in between to validate on.
+/// Kept within the UI overlay, which spans about +-28 degrees of yaw and +-16 of pitch.
+pub const CALIBRATION_TARGETS: [CalibrationTarget; 13] = [
+    target(0.0, 0.0, false),
+    target(12.0, -20.0, false),
+    target(12.0, 0.0, false),
+    target(12.0, 20.0, false),
+    target(0.0, 20.0, false),
+    target(-12.0, 20.0, false),
+    target(-12.0, 0.0, false),
+    target(-12.0, -20.0, false),
+    target(0.0, -20.0, false),
+    target(6.0, -10.0, true),
+    target(6.0, 10.0, true),
+    target(-6.0, 10.0, true),
+    target(-6.0, -10.0, true),
+];
+
+/// Time for the eyes to land on a new target, samples are ignored meanwhile.
+const TARGET_SETTLE: Duration = Duration::from_millis(800);
+/// Time samples are collected for each target.
+const TARGET_COLLECT: Duration = Duration::from_millis(1000);
+/// Targets with fewer samples for an eye are skipped for that eye.
+const MIN_TARGET_SAMPLES: usize = 5;
+
+/// Polynomial terms of raw pitch and yaw, quadratic or just affine.
+fn terms(pitch: f32, yaw: f32, count: usize) -> Vec<f32> {
+    let all = [1.0, pitch, yaw, pitch * pitch, pitch * yaw, yaw * yaw];
+    all[..count].to_vec()
+}
+
+/// Solves `a * x = b` with Gaussian elimination, `None` if `a` is singular.
+fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-9 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+
+        for row in col + 1..n {
+            let factor = a[row][col] / a[col][col];
+            for k in col..n {
+                a[row][k] -= factor * a[col][k];
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum = (row + 1..n).map(|k| a[row][k] * x[k]).sum::<f64>();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+/// Maps raw model angles to calibrated ones.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GazeMapping {
+    pitch: Vec<f32>,
+    yaw: Vec<f32>,
+}
+
+impl GazeMapping {
+    /// Least squares fit of `(raw, target)` pitch/yaw pairs. Needs at least 3 points,
+    /// quadratic terms are only used with enough points to not overfit.
+    pub fn fit(points: &[((f32, f32), (f32, f32))]) -> Option<Self> {
+        let count = match points.len() {
+            0..3 => return None,
+            3..8 => 3,
+            _ => 6,
+        };
+
+        let fit_axis = |target: fn(&(f32, f32)) -> f32| {
+            let mut ata = vec![vec![0.0f64; count]; count];
+            let mut atb = vec![0.0f64; count];
+            for ((pitch, yaw), expected) in points {
+                let row = terms(*pitch, *yaw, count);
+                for i in 0..count {
+                    for j in 0..count {
+                        ata[i][j] += row[i] as f64 * row[j] as f64;
+                    }
+                    atb[i] += row[i] as f64 * target(expected) as f64;
+                }
+            }
+            solve(ata, atb).map(|c| c.into_iter().map(|c| c as f32).collect::<Vec<_>>())
+        };
+
+        Some(Self {
+            pitch: fit_axis(|t| t.0)?,
+            yaw: fit_axis(|t| t.1)?,
+        })
+    }
+
+    pub fn apply(&self, pitch: f32, yaw: f32) -> (f32, f32) {
+        let row = terms(pitch, yaw, self.pitch.len());
+        let dot = |c: &[f32]| c.iter().zip(&row).map(|(c, t)| c * t).sum::<f32>();
+        (dot(&self.pitch), dot(&self.yaw))
+    }
+}
+
+/// Eyelid poses recorded by the user.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EyelidPose {
+    Closed,
+    Neutral,
+    Wide,
+}
+
+/// Raw eyelid values of one eye in each pose.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct EyelidCalibration {
+    pub closed: f32,
+    pub neutral: f32,
+    pub wide: f32,
+}
+
+impl Default for EyelidCalibration {
+    fn default() -> Self {
+        Self {
+            closed: 0.0,
+            neutral: EYELID_NEUTRAL_VALUE,
+            wide: 1.0,
+        }
+    }
+}
+
+impl EyelidCalibration {
+    fn from_json(value: &serde_json::Value) -> Option<Self> {
+        let get = |key: &str| value.get(key)?.as_f64().map(|v| v as f32);
+        Some(Self {
+            closed: get("closed")?,
+            neutral: get("neutral")?,
+            wide: get("wide")?,
+        })
+    }
+
+    fn to_json(self) -> serde_json::Value {
+        json!({
+            "closed": self.closed,
+            "neutral": self.neutral,
+            "wide": self.wide,
+        })
+    }
+
+    pub fn set(&mut self, pose: EyelidPose, value: f32) {
+        match pose {
+            EyelidPose::Closed => self.closed = value,
+            EyelidPose::Neutral => self.neutral = value,
+            EyelidPose::Wide => self.wide = value,
+        }
+    }
+
+    /// How far the eye is from neutral towards closed, 0.0..=1.0.
+    pub fn closed_amount(&self, eyelid: f32) -> f32 {
+        let range = (self.neutral - self.closed).max(f32::EPSILON);
+        ((self.neutral - eyelid) / range).clamp(0.0, 1.0)
+    }
+
+    /// How far the eye is from neutral towards wide open, 0.0..=1.0.
+    pub fn wide_amount(&self, eyelid: f32) -> f32 {
+        let range = (self.wide - self.neutral).max(f32::EPSILON);
+        ((eyelid - self.neutral) / range).clamp(0.0, 1.0)
+    }
+}
+
+fn eyelid_calibration_path() -> PathBuf {
+    crate::storage::data_dir().join("eyelid_calibration.json")
+}
+
+fn load_eyelid_calibration() -> Option<[EyelidCalibration; 2]> {
+    let json = std::fs::read_to_string(eyelid_calibration_path()).ok()?;
+    let Ok(json) = serde_json::from_str::<serde_json::Value>(&json) else {
+        warn!("Invalid eyelid calibration file, using defaults");
+        return None;
+    };
+
+    Some([
+        EyelidCalibration::from_json(json.get("left")?)?,
+        EyelidCalibration::from_json(json.get("right")?)?,
+    ])
+}
+
+/// Raw pitch/yaw samples of both eyes for each target.
+struct CalibrationSession {
+    started: Instant,
+    samples: Vec<[Vec<(f32, f32)>; 2]>,
+}
+
+impl CalibrationSession {
+    /// Index of the current target and whether samples are being collected.
+    fn position(&self) -> Option<(usize, f32, bool)> {
+        let per_target = TARGET_SETTLE + TARGET_COLLECT;
+        let elapsed = self.started.elapsed();
+        let index = (elapsed.as_secs_f32() / per_target.as_secs_f32()) as usize;
+        if index >= CALIBRATION_TARGETS.len() {
+            return None;
+        }
+
+        let in_target = elapsed.as_secs_f32() % per_target.as_secs_f32();
+        let progress = in_target / per_target.as_secs_f32();
+        Some((index, progress, in_target > TARGET_SETTLE.as_secs_f32()))
+    }
+
+    /// Median raw gaze of `eye` on each target, if there were enough samples.
+    fn medians(&self, eye: Eye) -> Vec<Option<(f32, f32)>> {
+        self.samples
+            .iter()
+            .map(|samples| {
+                let samples = &samples[eye as usize];
+                (samples.len() >= MIN_TARGET_SAMPLES).then(|| {
+                    (
+                        median(samples.iter().map(|s| s.0)),
+                        median(samples.iter().map(|s| s.1)),
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+/// Runs the calibration procedure and holds the resulting per eye mappings.
+#[derive(Default)]
+pub struct CalibrationControl {
+    mappings: Mutex<[Option<GazeMapping>; 2]>,
+    session: Mutex<Option<CalibrationSession>>,
+    status: Mutex<String>,
+    eyelids: Mutex<[EyelidCalibration; 2]>,
+    /// Recentering runs on the calibrated gaze, so a new mapping drops the old zero.
+    recenter: Arc<RecenterControl>,
+}
+
+impl CalibrationControl {
+    /// Starts with the eyelid calibration saved by a previous run, if any.
+    pub fn load(recenter: Arc<RecenterControl>) -> Self {
+        let control = Self {
+            recenter,
+            ..Default::default()
+        };
+        if let Some(eyelids) = load_eyelid_calibration() {
+            info!("Loaded eyelid calibration {eyelids:?}");
+            *control.eyelids.lock().unwrap() = eyelids;
+        }
+        control
+    }
+
+    pub fn eyelid(&self, eye: Eye) -> EyelidCalibration {
+        self.eyelids.lock().unwrap()[eye as usize]
+    }
+
+    /// Stores the current raw eyelid value of `eye` as `pose` and saves the calibration.
+    pub fn record_eyelid(&self, eye: Eye, pose: EyelidPose, value: f32) {
+        let mut eyelids = self.eyelids.lock().unwrap();
+        eyelids[eye as usize].set(pose, value);
+        Self::save_eyelids(&eyelids);
+    }
+
+    pub fn reset_eyelids(&self) {
+        let mut eyelids = self.eyelids.lock().unwrap();
+        *eyelids = Default::default();
+        Self::save_eyelids(&eyelids);
+    }
+
+    fn save_eyelids(eyelids: &[EyelidCalibration; 2]) {
+        let json = json!({
+            "left": eyelids[0].to_json(),
+            "right": eyelids[1].to_json(),
+        });
+        if let Err(err) = std::fs::write(eyelid_calibration_path(), json.to_string()) {
+            warn!("Failed to save eyelid calibration: {err}");
+        }
+    }
+
+    pub fn start(&self) {
+        *self.session.lock().unwrap() = Some(CalibrationSession {
+            started: Instant::now(),
+            samples: vec![Default::default(); CALIBRATION_TARGETS.len()],
+        });
+        self.set_status("Calibrating, follow the targets".to_string());
+    }
+
+    pub fn cancel(&self) {
+        if self.session.lock().unwrap().take().is_some() {
+            self.set_status("Calibration cancelled".to_string());
+        }
+    }
+
+    /// Goes back to the raw model output.
+    pub fn clear(&self) {
+        *self.mappings.lock().unwrap() = Default::default();
+        self.recenter.reset();
+        self.set_status("Not calibrated".to_string());
+    }
+
+    /// Target to show and how far along it is, 0.0..1.0. Finishes the session when done.
+    pub fn current_target(&self) -> Option<(CalibrationTarget, f32)> {
+        let position = self.session.lock().unwrap().as_ref()?.position();
+        match position {
+            Some((index, progress, _)) => Some((CALIBRATION_TARGETS[index], progress)),
+            None => {
+                self.finish();
+                None
+            }
+        }
+    }
+
+    /// Records an uncalibrated sample if a target is being collected.
+    pub fn add_sample(&self, eye: Eye, state: &EyeGazeState) {
+        if !state.valid {
+            return;
+        }
+
+        let mut guard = self.session.lock().unwrap();
+        let Some(session) = guard.as_mut() else {
+            return;
+        };
+
+        match session.position() {
+            Some((index, _, true)) => {
+                session.samples[index][eye as usize].push((state.pitch, state.yaw));
+            }
+            Some((_, _, false)) => {}
+            None => {
+                drop(guard);
+                self.finish();
+            }
+        }
+    }
+
+    pub fn apply(&self, eye: Eye, state: EyeGazeState) -> EyeGazeState {
+        let mappings = self.mappings.lock().unwrap();
+        let Some(mapping) = &mappings[eye as usize] else {
+            return state;
+        };
+
+        let (pitch, yaw) = mapping.apply(state.pitch, state.yaw);
+        EyeGazeState {
+            pitch,
+            yaw,
+            ..state
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.session.lock().unwrap().is_some()
+    }
+
+    /// Result of the last calibration, with the validation error of each eye.
+    pub fn status(&self) -> String {
+        self.status.lock().unwrap().clone()
+    }
+
+    fn set_status(&self, status: String) {
+        *self.status.lock().unwrap() = status;
+    }
+
+    fn finish(&self) {
+        let Some(session) = self.session.lock().unwrap().take() else {
+            return;
+        };
+
+        let mut report = Vec::new();
+        let mut mappings = self.mappings.lock().unwrap();
+
+        for (eye, name) in [(Eye::L, "Left"), (Eye::R, "Right")] {
+            let medians = session.medians(eye);
+            let pairs = |validation: bool| {
+                CALIBRATION_TARGETS
+                    .iter()
+                    .zip(&medians)
+                    .filter(|(target, _)| target.validation == validation)
+                    .filter_map(|(target, raw)| Some(((*raw)?, (target.pitch, target.yaw))))
+                    .collect::<Vec<_>>()
+            };
+
+            let Some(mapping) = GazeMapping::fit(&pairs(false)) else {
+                report.push(format!("{name}: not enough samples, kept the old mapping"));
+                continue;
+            };
+
+            let errors = pairs(true)
+                .into_iter()
+                .map(|((raw_pitch, raw_yaw), (pitch, yaw))| {
+                    let (mapped_pitch, mapped_yaw) = mapping.apply(raw_pitch, raw_yaw);
+                    (mapped_pitch - pitch).hypot(mapped_yaw - yaw)
+                })
+                .collect::<Vec<_>>();
+
+            report.push(if errors.is_empty() {
+                format!("{name}: calibrated, no validation samples")
+            } else {
+                let mean = errors.iter().sum::<f32>() / errors.len() as f32;
+                let max = errors.iter().copied().fold(0.0, f32::max);
+                format!("{name}: validation error mean {mean:.1} deg, max {max:.1} deg")
+            });
+
+            mappings[eye as usize] = Some(mapping);
+            self.recenter.reset_eye(eye);
+        }
+
+        self.set_status(report.join("\n"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::structs::ZERO_TIMESTAMP;
+
+    fn recentered() -> Arc<RecenterControl> {
+        let recenter = Arc::new(RecenterControl::default());
+        recenter.request();
+        for eye in [Eye::L, Eye::R] {
+            let state = EyeGazeState {
+                pitch: 3.0,
+                yaw: -4.0,
+                valid: true,
+                ..Default::default()
+            };
+            recenter.apply(eye, state, ZERO_TIMESTAMP);
+        }
+        assert_eq!(recenter.offset(Eye::L), (3.0, -4.0));
+        recenter
+    }
+
+    #[test]
+    fn finishing_resets_the_recenter_offsets() {
+        let recenter = recentered();
+        let control = CalibrationControl {
+            recenter: recenter.clone(),
+            ..Default::default()
+        };
+        let samples = CALIBRATION_TARGETS
+            .iter()
+            .map(|target| {
+                let samples = vec![(target.pitch, target.yaw); MIN_TARGET_SAMPLES];
+                [samples.clone(), samples]
+            })
+            .collect();
+        *control.session.lock().unwrap() = Some(CalibrationSession {
+            started: Instant::now(),
+            samples,
+        });
+
+        control.finish();
+
+        assert!(control.mappings.lock().unwrap().iter().all(Option::is_some));
+        assert_eq!(recenter.offset(Eye::L), (0.0, 0.0));
+        assert_eq!(recenter.offset(Eye::R), (0.0, 0.0));
+    }
+
+    #[test]
+    fn clearing_resets_the_recenter_offsets() {
+        let recenter = recentered();
+        let control = CalibrationControl {
+            recenter: recenter.clone(),
+            ..Default::default()
+        };
+
+        control.clear();
+
+        assert_eq!(recenter.offset(Eye::L), (0.0, 0.0));
+        assert_eq!(recenter.offset(Eye::R), (0.0, 0.0));
+    }
+}
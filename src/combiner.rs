@@ -0,0 +1,554 @@
+use std::time::Duration;
+
+use crate::calibration::EyelidCalibration;
+use crate::post_processing::{PostProcessSettings, linked_direction};
+use crate::prediction::EyePredictor;
+use crate::structs::{
+    CombinedEyeGazeState, Eye, EyeGazeState, EyeTrackingState, EyesGazeState, Timestamp,
+    ZERO_TIMESTAMP,
+};
+use crate::tracking::{EyeFallback, EyeTracker, TrackingSettings};
+use crate::vergence::{VergenceEstimator, VergenceSettings};
+
+/// Samples up to this much older than the last one of the same eye arrived out of order
+/// and are dropped. Anything older means the clock jumped back, the eye starts over.
+const MAX_REORDER: Duration = Duration::from_millis(100);
+
+/// Maps raw pupil size onto 0.0..=1.0 using the range observed for the current user.
+/// The extremes slowly move towards each other, so a single outlier doesn't stick forever.
+struct PupilNormalizer {
+    min: f32,
+    max: f32,
+}
+
+impl PupilNormalizer {
+    /// Fraction of the range the extremes move inwards per sample.
+    const RELAX_RATE: f32 = 0.0005;
+    /// Below this range the normalized output is meaningless.
+    const MIN_RANGE: f32 = 0.005;
+    /// Pupil size is measured only on reasonably open and trusted eyes.
+    const MIN_EYELID: f32 = 0.4;
+    const MIN_CONFIDENCE: f32 = 0.5;
+
+    fn new() -> Self {
+        Self {
+            min: f32::INFINITY,
+            max: f32::NEG_INFINITY,
+        }
+    }
+
+    fn update(&mut self, state: &EyeGazeState) {
+        if !state.valid
+            || !state.pupil.is_finite()
+            || state.eyelid < Self::MIN_EYELID
+            || state.confidence < Self::MIN_CONFIDENCE
+        {
+            return;
+        }
+
+        if self.max - self.min > Self::MIN_RANGE {
+            let relax = (self.max - self.min) * Self::RELAX_RATE;
+            self.min += relax;
+            self.max -= relax;
+        }
+
+        self.min = self.min.min(state.pupil);
+        self.max = self.max.max(state.pupil);
+    }
+
+    fn normalize(&self, pupil: f32) -> f32 {
+        let range = self.max - self.min;
+        if !(range > Self::MIN_RANGE) {
+            return 0.5;
+        }
+        ((pupil - self.min) / range).clamp(0.0, 1.0)
+    }
+}
+
+/// Latest sample of one eye and what is derived from its history.
+struct EyeChannel {
+    state: EyeGazeState,
+    time: Timestamp,
+    pupil: PupilNormalizer,
+    predictor: EyePredictor,
+    tracker: EyeTracker,
+}
+
+impl EyeChannel {
+    fn new() -> Self {
+        Self {
+            state: EyeGazeState::default(),
+            time: ZERO_TIMESTAMP,
+            pupil: PupilNormalizer::new(),
+            predictor: EyePredictor::default(),
+            tracker: EyeTracker::default(),
+        }
+    }
+
+    fn push(&mut self, state: EyeGazeState, timestamp: Timestamp) {
+        if let Ok(behind) = self.time.duration_since(timestamp) {
+            if behind > MAX_REORDER {
+                // The pupil range belongs to the user, not to the clock.
+                self.predictor = EyePredictor::default();
+                self.tracker = EyeTracker::default();
+            } else if !behind.is_zero() {
+                return;
+            }
+        }
+
+        self.state = state;
+        self.time = timestamp;
+        self.pupil.update(&state);
+        self.predictor.update(&state, timestamp);
+        self.tracker.update(&state, timestamp);
+    }
+}
+
+/// Everything the combination depends on besides the samples.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CombineSettings {
+    pub tracking: TrackingSettings,
+    pub vergence: VergenceSettings,
+    pub post_process: PostProcessSettings,
+    pub eyelids: [EyelidCalibration; 2],
+}
+
+/// Merges the processed samples of both eyes into one gaze state, without any I/O or
+/// clock access, so it can be driven by tests as well as by `process_gaze`.
+pub struct GazeCombiner {
+    l: EyeChannel,
+    r: EyeChannel,
+    vergence: VergenceEstimator,
+}
+
+impl Default for GazeCombiner {
+    fn default() -> Self {
+        Self {
+            l: EyeChannel::new(),
+            r: EyeChannel::new(),
+            vergence: VergenceEstimator::default(),
+        }
+    }
+}
+
+impl GazeCombiner {
+    /// `sample` is `None` when no frames arrived for a while, the eyes then age against
+    /// `clock` so they turn into degraded and lost eyes instead of a frozen gaze.
+    pub fn combine(
+        &mut self,
+        sample: Option<EyesGazeState>,
+        clock: Timestamp,
+        settings: &CombineSettings,
+    ) -> CombinedEyeGazeState {
+        match sample {
+            Some(EyesGazeState::Both {
+                l_state,
+                r_state,
+                timestamp,
+            }) => {
+                self.l.push(l_state, timestamp);
+                self.r.push(r_state, timestamp);
+            }
+            Some(EyesGazeState::Mono {
+                eye: Eye::L,
+                state,
+                timestamp,
+            }) => self.l.push(state, timestamp),
+            Some(EyesGazeState::Mono {
+                eye: Eye::R,
+                state,
+                timestamp,
+            }) => self.r.push(state, timestamp),
+            None => {}
+        }
+
+        let (l_time, r_time) = (self.l.time, self.r.time);
+        let newest = l_time.max(r_time);
+        let now = match sample {
+            Some(_) => newest,
+            None => clock.max(newest),
+        };
+
+        let tracking_settings = &settings.tracking;
+        let vergence_settings = &settings.vergence;
+        let post_settings = &settings.post_process;
+        let [l_eyelid, r_eyelid] = &settings.eyelids;
+
+        let (l_pitch_velocity, l_yaw_velocity) = self.l.predictor.velocity();
+        let (r_pitch_velocity, r_yaw_velocity) = self.r.predictor.velocity();
+
+        let l_tracking = self.l.tracker.evaluate(now, tracking_settings);
+        let r_tracking = self.r.tracker.evaluate(now, tracking_settings);
+        let l_tracked = l_tracking == EyeTrackingState::Tracking;
+        let r_tracked = r_tracking == EyeTrackingState::Tracking;
+
+        let l_mirrors = !l_tracked
+            && r_tracked
+            && tracking_settings.fallback(l_tracking) == EyeFallback::Mirror;
+        let r_mirrors = !r_tracked
+            && l_tracked
+            && tracking_settings.fallback(r_tracking) == EyeFallback::Mirror;
+
+        // Extrapolate the eye that is behind, so both describe the same moment.
+        let l_state = if l_tracked && r_tracked && l_time < r_time {
+            self.l.predictor.predict(r_time).unwrap_or(self.l.state)
+        } else {
+            self.l.state
+        };
+        let r_state = if l_tracked && r_tracked && r_time < l_time {
+            self.r.predictor.predict(l_time).unwrap_or(self.r.state)
+        } else {
+            self.r.state
+        };
+
+        let mirrored = |state: EyeGazeState| EyeGazeState {
+            confidence: 0.0,
+            valid: false,
+            ..state
+        };
+
+        // Mirrored eyes also take the eyelid calibration, pupil range and
+        // velocity of their source, fallbacks don't move.
+        let (l_out, l_source, l_velocity) = if l_tracked {
+            (l_state, Eye::L, (l_pitch_velocity, l_yaw_velocity))
+        } else if l_mirrors {
+            (
+                mirrored(r_state),
+                Eye::R,
+                (r_pitch_velocity, r_yaw_velocity),
+            )
+        } else {
+            let fallback = tracking_settings.fallback(l_tracking);
+            (
+                self.l
+                    .tracker
+                    .fallback_state(fallback, now, tracking_settings, l_eyelid.neutral),
+                Eye::L,
+                (0.0, 0.0),
+            )
+        };
+        let (r_out, r_source, r_velocity) = if r_tracked {
+            (r_state, Eye::R, (r_pitch_velocity, r_yaw_velocity))
+        } else if r_mirrors {
+            (
+                mirrored(l_state),
+                Eye::L,
+                (l_pitch_velocity, l_yaw_velocity),
+            )
+        } else {
+            let fallback = tracking_settings.fallback(r_tracking);
+            (
+                self.r
+                    .tracker
+                    .fallback_state(fallback, now, tracking_settings, r_eyelid.neutral),
+                Eye::R,
+                (0.0, 0.0),
+            )
+        };
+
+        let eyelid_of = |eye: Eye| &settings.eyelids[eye as usize];
+        let pupil_of = |eye: Eye| match eye {
+            Eye::L => &self.l.pupil,
+            Eye::R => &self.r.pupil,
+        };
+
+        let gaze_linked = post_settings.link_gaze(
+            l_tracked && r_tracked,
+            l_out.confidence,
+            r_out.confidence,
+            l_out.yaw - r_out.yaw,
+        );
+
+        let (avg_pitch, avg_yaw, (l_pitch, r_pitch)) = if gaze_linked {
+            let (pitch, yaw) = linked_direction(&l_out, &r_out);
+            (pitch, yaw, (pitch, pitch))
+        } else {
+            (
+                (l_out.pitch + r_out.pitch) / 2.0,
+                (l_out.yaw + r_out.yaw) / 2.0,
+                vergence_settings.eye_pitches(l_out.pitch, r_out.pitch),
+            )
+        };
+
+        // Vergence needs both eyes, otherwise the last distance is kept.
+        let focus_distance = if l_tracked && r_tracked && !gaze_linked {
+            self.vergence
+                .update(l_out.yaw - r_out.yaw, now, vergence_settings)
+        } else {
+            self.vergence.hold(vergence_settings)
+        };
+
+        // Linked eyes look in parallel. Otherwise each eye keeps its own yaw, smoothing
+        // it with the focus distance would add lag and rule out diverging eyes.
+        let (l_yaw, r_yaw) = if gaze_linked {
+            (avg_yaw, avg_yaw)
+        } else {
+            (l_out.yaw, r_out.yaw)
+        };
+
+        let mut combined = CombinedEyeGazeState {
+            pitch: avg_pitch,
+            l_yaw,
+            r_yaw,
+            l_pitch,
+            r_pitch,
+            l_eyelid: l_out.eyelid,
+            r_eyelid: r_out.eyelid,
+
+            l_closed: eyelid_of(l_source).closed_amount(l_out.eyelid),
+            r_closed: eyelid_of(r_source).closed_amount(r_out.eyelid),
+            l_wide: eyelid_of(l_source).wide_amount(l_out.eyelid),
+            r_wide: eyelid_of(r_source).wide_amount(r_out.eyelid),
+            l_squint: 0.0,
+            r_squint: 0.0,
+            eyelids_synced: false,
+
+            gaze_pitch: avg_pitch,
+            gaze_yaw: avg_yaw,
+            gaze_origin: vergence_settings.gaze_origin(),
+            gaze_linked,
+
+            focus_distance,
+            ipd: vergence_settings.ipd,
+
+            l_confidence: l_out.confidence,
+            r_confidence: r_out.confidence,
+
+            // Pupils react to light together, so mirroring is fine here.
+            l_pupil: pupil_of(l_source).normalize(l_out.pupil),
+            r_pupil: pupil_of(r_source).normalize(r_out.pupil),
+
+            // Blinks are detected before the combination, the caller fills this in.
+            blink_rate: 0.0,
+
+            l_valid: l_tracked,
+            r_valid: r_tracked,
+            l_tracking,
+            r_tracking,
+
+            pitch_velocity: (l_velocity.0 + r_velocity.0) / 2.0,
+            l_yaw_velocity: l_velocity.1,
+            r_yaw_velocity: r_velocity.1,
+
+            timestamp: now,
+        };
+        post_settings.apply_eyelids(&mut combined);
+        combined
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(ms: u64) -> Timestamp {
+        ZERO_TIMESTAMP + Duration::from_secs(1_000) + Duration::from_millis(ms)
+    }
+
+    fn eye(yaw: f32) -> EyeGazeState {
+        EyeGazeState {
+            yaw,
+            confidence: 1.0,
+            valid: true,
+            ..Default::default()
+        }
+    }
+
+    #[derive(Clone, Copy)]
+    enum Step {
+        Both(u64, f32, f32),
+        Mono(Eye, u64, f32),
+        Rejected(Eye, u64),
+        Idle(u64),
+    }
+
+    struct Case {
+        name: &'static str,
+        steps: &'static [Step],
+        l_tracking: EyeTrackingState,
+        r_tracking: EyeTrackingState,
+        timestamp: u64,
+        /// Expected combined gaze yaw.
+        gaze_yaw: f32,
+    }
+
+    use EyeTrackingState::{Degraded, Lost, Tracking};
+    use Step::*;
+
+    const CASES: &[Case] = &[
+        Case {
+            name: "nothing received",
+            steps: &[Idle(0)],
+            l_tracking: Lost,
+            r_tracking: Lost,
+            timestamp: 0,
+            gaze_yaw: 0.0,
+        },
+        Case {
+            name: "both eyes",
+            steps: &[Both(0, 4.0, 2.0)],
+            l_tracking: Tracking,
+            r_tracking: Tracking,
+            timestamp: 0,
+            gaze_yaw: 3.0,
+        },
+        Case {
+            name: "mono left only, right mirrors it",
+            steps: &[Mono(Eye::L, 0, 5.0)],
+            l_tracking: Tracking,
+            r_tracking: Lost,
+            timestamp: 0,
+            gaze_yaw: 5.0,
+        },
+        Case {
+            name: "mono right only, left mirrors it",
+            steps: &[Mono(Eye::R, 0, -5.0)],
+            l_tracking: Lost,
+            r_tracking: Tracking,
+            timestamp: 0,
+            gaze_yaw: -5.0,
+        },
+        Case {
+            name: "alternating mono eyes within the timeout",
+            steps: &[Mono(Eye::L, 0, 2.0), Mono(Eye::R, 20, 2.0)],
+            l_tracking: Tracking,
+            r_tracking: Tracking,
+            timestamp: 20,
+            gaze_yaw: 2.0,
+        },
+        Case {
+            name: "right eye behind the left",
+            steps: &[Mono(Eye::L, 40, 2.0), Mono(Eye::R, 30, 2.0)],
+            l_tracking: Tracking,
+            r_tracking: Tracking,
+            timestamp: 40,
+            gaze_yaw: 2.0,
+        },
+        Case {
+            name: "right eye timed out",
+            steps: &[Both(0, 0.0, 0.0), Mono(Eye::L, 100, 6.0)],
+            l_tracking: Tracking,
+            r_tracking: Degraded,
+            timestamp: 100,
+            gaze_yaw: 6.0,
+        },
+        Case {
+            name: "right eye lost",
+            steps: &[Both(0, 0.0, 0.0), Mono(Eye::L, 2_000, 6.0)],
+            l_tracking: Tracking,
+            r_tracking: Lost,
+            timestamp: 2_000,
+            gaze_yaw: 6.0,
+        },
+        Case {
+            name: "rejected sample degrades the eye right away",
+            steps: &[Both(0, 2.0, 2.0), Rejected(Eye::R, 10)],
+            l_tracking: Tracking,
+            r_tracking: Degraded,
+            timestamp: 10,
+            gaze_yaw: 2.0,
+        },
+        Case {
+            name: "idle within the timeout keeps tracking",
+            steps: &[Both(0, 1.0, 1.0), Idle(30)],
+            l_tracking: Tracking,
+            r_tracking: Tracking,
+            timestamp: 30,
+            gaze_yaw: 1.0,
+        },
+        Case {
+            name: "both eyes stopped, eased to neutral",
+            steps: &[Both(0, 8.0, 8.0), Idle(1_500), Idle(3_000)],
+            l_tracking: Lost,
+            r_tracking: Lost,
+            timestamp: 3_000,
+            gaze_yaw: 0.0,
+        },
+        Case {
+            name: "degraded eyes stay eased when lost",
+            steps: &[Both(0, 8.0, 8.0), Idle(60), Idle(1_100)],
+            l_tracking: Lost,
+            r_tracking: Lost,
+            timestamp: 1_100,
+            gaze_yaw: 0.0,
+        },
+        Case {
+            name: "late sample is dropped",
+            steps: &[Both(100, 3.0, 3.0), Mono(Eye::L, 50, -9.0)],
+            l_tracking: Tracking,
+            r_tracking: Tracking,
+            timestamp: 100,
+            gaze_yaw: 3.0,
+        },
+        Case {
+            name: "clock jumped back",
+            steps: &[Both(5_000, 3.0, 3.0), Both(1_000, -2.0, -2.0)],
+            l_tracking: Tracking,
+            r_tracking: Tracking,
+            timestamp: 1_000,
+            gaze_yaw: -2.0,
+        },
+        Case {
+            name: "idle clock behind the samples",
+            steps: &[Both(1_000, 1.0, 1.0), Idle(0)],
+            l_tracking: Tracking,
+            r_tracking: Tracking,
+            timestamp: 1_000,
+            gaze_yaw: 1.0,
+        },
+    ];
+
+    #[test]
+    fn combine() {
+        let settings = CombineSettings::default();
+
+        for case in CASES {
+            let mut combiner = GazeCombiner::default();
+            let mut combined = CombinedEyeGazeState::default();
+
+            for step in case.steps {
+                let (sample, clock) = match *step {
+                    Both(ms, l_yaw, r_yaw) => (
+                        Some(EyesGazeState::Both {
+                            l_state: eye(l_yaw),
+                            r_state: eye(r_yaw),
+                            timestamp: at(ms),
+                        }),
+                        at(ms),
+                    ),
+                    Mono(e, ms, yaw) => (
+                        Some(EyesGazeState::Mono {
+                            eye: e,
+                            state: eye(yaw),
+                            timestamp: at(ms),
+                        }),
+                        at(ms),
+                    ),
+                    Rejected(e, ms) => (
+                        Some(EyesGazeState::Mono {
+                            eye: e,
+                            state: EyeGazeState::default(),
+                            timestamp: at(ms),
+                        }),
+                        at(ms),
+                    ),
+                    Idle(ms) => (None, at(ms)),
+                };
+                combined = combiner.combine(sample, clock, &settings);
+            }
+
+            let name = case.name;
+            assert_eq!(combined.l_tracking, case.l_tracking, "{name}");
+            assert_eq!(combined.r_tracking, case.r_tracking, "{name}");
+            assert_eq!(combined.l_valid, case.l_tracking == Tracking, "{name}");
+            assert_eq!(combined.r_valid, case.r_tracking == Tracking, "{name}");
+            assert_eq!(combined.timestamp, at(case.timestamp), "{name}");
+            assert!(
+                (combined.gaze_yaw - case.gaze_yaw).abs() < 0.01,
+                "{name}: gaze yaw {} instead of {}",
+                combined.gaze_yaw,
+                case.gaze_yaw
+            );
+        }
+    }
+}
@@ -0,0 +1,207 @@
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+
+use futures::SinkExt;
+use log::{error, info, warn};
+use tokio::{net::TcpListener, task::JoinHandle};
+use tokio_stream::StreamExt;
+use tokio_util::codec::{Decoder, LinesCodec};
+
+use crate::app::App;
+#[cfg(feature = "inference")]
+use crate::inference::InferenceControl;
+#[cfg(feature = "inference")]
+use crate::osc_sender::{OscControl, OscDestination};
+#[cfg(feature = "inference")]
+use crate::recenter::RecenterControl;
+
+pub const CONTROL_SERVER_PORT: u16 = 7071;
+
+/// Everything the control server is allowed to poke.
+#[derive(Clone)]
+pub struct ControlContext {
+    #[cfg(feature = "inference")]
+    pub inference_control: Arc<InferenceControl>,
+    #[cfg(feature = "inference")]
+    pub recenter_control: Arc<RecenterControl>,
+    #[cfg(feature = "inference")]
+    pub osc_control: Arc<OscControl>,
+}
+
+impl ControlContext {
+    pub fn new(app: &App) -> Self {
+        Self {
+            #[cfg(feature = "inference")]
+            inference_control: app.inference_control.clone(),
+            #[cfg(feature = "inference")]
+            recenter_control: app.recenter_control.clone(),
+            #[cfg(feature = "inference")]
+            osc_control: app.osc_control.clone(),
+        }
+    }
+}
+
+/// Line based JSON commands, e.g. `{"command": "reload_model"}`.
+/// Every command is answered with a single line, either `ok` or `error: <reason>`.
+/// There is no authentication, so other hosts can only connect with `allow_lan`.
+pub fn start_control_server(context: ControlContext, allow_lan: bool) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let host = if allow_lan {
+            Ipv4Addr::UNSPECIFIED
+        } else {
+            Ipv4Addr::LOCALHOST
+        };
+        let listener = match TcpListener::bind((host, CONTROL_SERVER_PORT)).await {
+            Ok(listener) => listener,
+            Err(err) => {
+                error!("Failed to start control server: {err:?}");
+                return;
+            }
+        };
+
+        loop {
+            let (socket, address) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    warn!("Failed to accept control connection: {err:?}");
+                    continue;
+                }
+            };
+
+            info!("Control connection from {address}");
+
+            let context = context.clone();
+
+            tokio::spawn(async move {
+                let mut framed = LinesCodec::new().framed(socket);
+
+                while let Some(message) = framed.next().await {
+                    let line = match message {
+                        Ok(line) => line,
+                        Err(err) => {
+                            warn!("Control socket closed with error: {err:?}");
+                            break;
+                        }
+                    };
+
+                    let reply = match handle_command(&context, &line) {
+                        Ok(()) => "ok".to_string(),
+                        Err(reason) => format!("error: {reason}"),
+                    };
+
+                    if framed.send(reply).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+    })
+}
+
+fn handle_command(context: &ControlContext, line: &str) -> Result<(), String> {
+    let json: serde_json::Value =
+        serde_json::from_str(line).map_err(|e| format!("invalid JSON: {e}"))?;
+
+    let command = json
+        .get("command")
+        .and_then(|c| c.as_str())
+        .ok_or("missing \"command\"")?;
+
+    match command {
+        #[cfg(feature = "inference")]
+        "reload_model" => {
+            context.inference_control.request_reload();
+            Ok(())
+        }
+        #[cfg(feature = "inference")]
+        "set_model" => {
+            let path = json
+                .get("path")
+                .and_then(|p| p.as_str())
+                .ok_or("missing \"path\"")?;
+            context
+                .inference_control
+                .update_settings(|settings| settings.model_path = path.into());
+            Ok(())
+        }
+        #[cfg(feature = "inference")]
+        "set_threads" => {
+            let threads = json
+                .get("threads")
+                .and_then(|t| t.as_u64())
+                .filter(|&t| t > 0)
+                .ok_or("missing or invalid \"threads\"")?;
+            context
+                .inference_control
+                .update_settings(|settings| settings.threads_per_eye = threads as usize);
+            Ok(())
+        }
+        #[cfg(feature = "inference")]
+        "set_session_options" => {
+            use clap::ValueEnum;
+
+            let execution_provider = json
+                .get("execution_provider")
+                .and_then(|p| p.as_str())
+                .map(|p| {
+                    crate::inference::ExecutionProviderKind::from_str(p, true)
+                        .map_err(|e| format!("invalid \"execution_provider\": {e}"))
+                })
+                .transpose()?;
+            let get_bool = |key: &str| json.get(key).and_then(|v| v.as_bool());
+            let inter_threads = json
+                .get("inter_threads")
+                .and_then(|t| t.as_u64())
+                .map(|t| t as usize);
+
+            context.inference_control.update_settings(|settings| {
+                if let Some(execution_provider) = execution_provider {
+                    settings.execution_provider = execution_provider;
+                }
+                if let Some(inter_threads) = inter_threads {
+                    settings.inter_threads = inter_threads;
+                }
+                if let Some(parallel_execution) = get_bool("parallel_execution") {
+                    settings.parallel_execution = parallel_execution;
+                }
+                if let Some(memory_arena) = get_bool("memory_arena") {
+                    settings.memory_arena = memory_arena;
+                }
+                if let Some(intra_op_spinning) = get_bool("intra_op_spinning") {
+                    settings.intra_op_spinning = intra_op_spinning;
+                }
+                if let Some(inter_op_spinning) = get_bool("inter_op_spinning") {
+                    settings.inter_op_spinning = inter_op_spinning;
+                }
+            });
+            Ok(())
+        }
+        #[cfg(feature = "inference")]
+        "recenter" => {
+            context.recenter_control.request();
+            Ok(())
+        }
+        #[cfg(feature = "inference")]
+        "reset_recenter" => {
+            context.recenter_control.reset();
+            Ok(())
+        }
+        #[cfg(feature = "inference")]
+        "set_osc_destinations" => {
+            let destinations = json
+                .get("destinations")
+                .and_then(|d| d.as_array())
+                .ok_or("missing \"destinations\"")?
+                .iter()
+                .map(|d| {
+                    d.as_str()
+                        .ok_or("destinations must be strings".to_string())?
+                        .parse::<OscDestination>()
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            context.osc_control.set_destinations(destinations);
+            Ok(())
+        }
+        _ => Err(format!("unknown command {command:?}")),
+    }
+}
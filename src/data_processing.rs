@@ -1,40 +1,176 @@
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use async_broadcast::{Receiver, RecvError, Sender};
 use log::{error, warn};
 use tokio::task::JoinHandle;
 
-use crate::structs::{CombinedEyeGazeState, Eye, EyeGazeState, EyesGazeState, ZERO_TIMESTAMP};
+use crate::app::App;
+use crate::blink::{BlinkDetector, BlinkEvent};
+use crate::calibration::{CalibrationControl, EyelidCalibration};
+use crate::combiner::{CombineSettings, GazeCombiner};
+use crate::eye_movements::{MovementControl, MovementSettings, SaccadeDetector};
+use crate::filters::{FilterChain, FilterControl, FilterSettings};
+use crate::post_processing::PostProcessControl;
+use crate::recenter::RecenterControl;
+use crate::structs::{CombinedEyeGazeState, Eye, EyeGazeState, EyesGazeState, Timestamp};
+use crate::tracking::TrackingControl;
+use crate::vergence::VergenceControl;
+
+/// Without frames the output is still updated this often, so stopped cameras turn into
+/// degraded and lost eyes instead of a frozen gaze.
+const IDLE_TICK: Duration = Duration::from_millis(100);
+
+/// Filter chains of a single eye.
+#[derive(Default)]
+struct EyeFilters {
+    pitch: FilterChain,
+    yaw: FilterChain,
+    eyelid: FilterChain,
+    restart_on_saccades: bool,
+    saccades: SaccadeDetector,
+    last_timestamp: Option<Timestamp>,
+}
+
+impl EyeFilters {
+    /// Filters still start over from the current value after a gap this long.
+    const RESET_GAP: Duration = Duration::from_millis(500);
+
+    fn configure(&mut self, settings: &FilterSettings) {
+        self.pitch.configure(&settings.pitch);
+        self.yaw.configure(&settings.yaw);
+        self.eyelid.configure(&settings.eyelid);
+        self.restart_on_saccades = settings.restart_on_saccades;
+    }
+
+    /// Rejected samples hold old values, they are passed through without touching the state.
+    fn apply(
+        &mut self,
+        state: EyeGazeState,
+        timestamp: Timestamp,
+        movement: &MovementSettings,
+    ) -> EyeGazeState {
+        if !state.valid {
+            return state;
+        }
+
+        let delta = self
+            .last_timestamp
+            .and_then(|last| timestamp.duration_since(last).ok())
+            .unwrap_or_default();
+        self.last_timestamp = Some(timestamp);
 
-const EYE_TIMEOUT: Duration = Duration::from_millis(50);
+        if delta > Self::RESET_GAP {
+            self.pitch.reset();
+            self.yaw.reset();
+            self.eyelid.reset();
+        }
+
+        let delta_secs = delta.as_secs_f32();
+
+        let saccade = self
+            .saccades
+            .update(timestamp, (state.pitch, state.yaw), movement);
+        if self.restart_on_saccades && saccade == Some(true) {
+            self.pitch.reset();
+            self.yaw.reset();
+        }
 
+        EyeGazeState {
+            pitch: self.pitch.apply(state.pitch, delta_secs),
+            yaw: self.yaw.apply(state.yaw, delta_secs),
+            eyelid: self.eyelid.apply(state.eyelid, delta_secs),
+            ..state
+        }
+    }
+}
+
+/// Everything of a single eye that runs before the eyes are combined.
+struct EyeProcessing {
+    eye: Eye,
+    filters: EyeFilters,
+    blink: BlinkDetector,
+}
+
+impl EyeProcessing {
+    fn new(eye: Eye) -> Self {
+        Self {
+            eye,
+            filters: EyeFilters::default(),
+            blink: BlinkDetector::new(eye),
+        }
+    }
+
+    fn process(
+        &mut self,
+        state: EyeGazeState,
+        timestamp: Timestamp,
+        controls: &GazeControls,
+        eyelid: &EyelidCalibration,
+        blink_events: &mut Vec<BlinkEvent>,
+    ) -> EyeGazeState {
+        // Blinks are detected on the raw eyelid, and the held gaze keeps the meaningless
+        // gaze of a closed eye out of the filters.
+        let state = self.blink.update(state, timestamp, eyelid, blink_events);
+        let state = self
+            .filters
+            .apply(state, timestamp, &controls.movement.settings());
+
+        // Calibration fits on the filtered, but not yet mapped, gaze.
+        controls.calibration.add_sample(self.eye, &state);
+        let state = controls.calibration.apply(self.eye, state);
+
+        // Headset slip shifts the calibrated gaze as a whole.
+        controls.recenter.apply(self.eye, state, timestamp)
+    }
+}
+
+/// Runtime settings `process_gaze` reads, shared with the UI.
+#[derive(Clone)]
+pub struct GazeControls {
+    pub filter: Arc<FilterControl>,
+    pub calibration: Arc<CalibrationControl>,
+    pub recenter: Arc<RecenterControl>,
+    pub vergence: Arc<VergenceControl>,
+    pub tracking: Arc<TrackingControl>,
+    pub post_process: Arc<PostProcessControl>,
+    pub movement: Arc<MovementControl>,
+}
+
+impl GazeControls {
+    pub fn new(app: &App) -> Self {
+        Self {
+            filter: app.filter_control.clone(),
+            calibration: app.calibration_control.clone(),
+            recenter: app.recenter_control.clone(),
+            vergence: app.vergence_control.clone(),
+            tracking: app.tracking_control.clone(),
+            post_process: app.post_process_control.clone(),
+            movement: app.movement_control.clone(),
+        }
+    }
+}
+
+/// Blink-detects, filters and calibrates each eye, then leaves the rest to `GazeCombiner`.
 pub fn process_gaze(
     mut rx: Receiver<EyesGazeState>,
     tx: Sender<CombinedEyeGazeState>,
+    blink_tx: Sender<BlinkEvent>,
+    controls: GazeControls,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
-        // const PY_BETA: f32 = 0.3;
-        // const PY_FCMIN: f32 = 0.5;
-        // let mut filter_pitch = OneEuroFilter::new(0.0, PY_FCMIN, PY_FCMIN, PY_BETA);
-        // let mut filter_yaw = OneEuroFilter::new(0.0, PY_FCMIN, PY_FCMIN, PY_BETA);
-
-        // const EL_BETA: f32 = 1.0;
-        // const EL_FCMIN: f32 = 3.0;
-        // let mut filter_eyelid = OneEuroFilter::new(0.0, EL_FCMIN, EL_FCMIN, EL_BETA);
+        let mut l_eye = EyeProcessing::new(Eye::L);
+        let mut r_eye = EyeProcessing::new(Eye::R);
+        let mut filter_generation = None;
+        let mut blink_events = Vec::new();
 
-        // let last_timestamp = SystemTime::now();
-
-        let mut l_state = EyeGazeState::default();
-        let mut l_time = ZERO_TIMESTAMP;
-
-        let mut r_state = EyeGazeState::default();
-        let mut r_time = ZERO_TIMESTAMP;
+        let mut combiner = GazeCombiner::default();
 
         loop {
             let eyes_gaze = loop {
-                match rx.recv_direct().await {
-                    Ok(eyes_frame) => break eyes_frame,
-                    Err(e) => match e {
+                match tokio::time::timeout(IDLE_TICK, rx.recv_direct()).await {
+                    Ok(Ok(eyes_frame)) => break Some(eyes_frame),
+                    Ok(Err(e)) => match e {
                         RecvError::Overflowed(skipped) => {
                             warn!("Skipped {skipped} frames");
                             continue;
@@ -44,156 +180,94 @@ pub fn process_gaze(
                             return;
                         }
                     },
+                    Err(_) => break None,
                 }
             };
 
-            match eyes_gaze {
-                EyesGazeState::Both {
-                    l_state: new_l_state,
-                    r_state: new_r_state,
-                    timestamp,
-                } => {
-                    l_state = new_l_state;
-                    r_state = new_r_state;
+            if filter_generation != Some(controls.filter.generation()) {
+                filter_generation = Some(controls.filter.generation());
+                let settings = controls.filter.settings();
+                l_eye.filters.configure(&settings);
+                r_eye.filters.configure(&settings);
+            }
+
+            let settings = CombineSettings {
+                tracking: controls.tracking.settings(),
+                vergence: controls.vergence.settings(),
+                post_process: controls.post_process.settings(),
+                eyelids: [
+                    controls.calibration.eyelid(Eye::L),
+                    controls.calibration.eyelid(Eye::R),
+                ],
+            };
+            let [l_eyelid, r_eyelid] = &settings.eyelids;
 
-                    l_time = timestamp;
-                    r_time = timestamp;
-                }
-                EyesGazeState::Mono {
+            let processed = match eyes_gaze {
+                Some(EyesGazeState::Both {
+                    l_state,
+                    r_state,
+                    timestamp,
+                }) => Some(EyesGazeState::Both {
+                    l_state: l_eye.process(
+                        l_state,
+                        timestamp,
+                        &controls,
+                        l_eyelid,
+                        &mut blink_events,
+                    ),
+                    r_state: r_eye.process(
+                        r_state,
+                        timestamp,
+                        &controls,
+                        r_eyelid,
+                        &mut blink_events,
+                    ),
+                    timestamp,
+                }),
+                Some(EyesGazeState::Mono {
                     eye,
                     state,
                     timestamp,
-                } => match eye {
-                    Eye::L => {
-                        l_state = state;
-                        l_time = timestamp;
-                    }
-                    Eye::R => {
-                        r_state = state;
-                        r_time = timestamp;
-                    }
-                },
-            };
-
-            let combined_gaze = 'combined: {
-                // Left eye has timed out.
-                if r_time > l_time && r_time.duration_since(l_time).unwrap() > EYE_TIMEOUT {
-                    break 'combined CombinedEyeGazeState {
-                        pitch: r_state.pitch,
-                        l_yaw: r_state.yaw,
-                        r_yaw: r_state.yaw,
-                        l_eyelid: r_state.eyelid,
-                        r_eyelid: r_state.eyelid,
-
-                        gaze_pitch: r_state.pitch,
-                        gaze_yaw: r_state.yaw,
-
-                        timestamp: r_time,
-                    };
-                }
-
-                // Right eye has timed out.
-                if l_time > r_time && l_time.duration_since(r_time).unwrap() > EYE_TIMEOUT {
-                    break 'combined CombinedEyeGazeState {
-                        pitch: l_state.pitch,
-                        l_yaw: l_state.yaw,
-                        r_yaw: l_state.yaw,
-                        l_eyelid: l_state.eyelid,
-                        r_eyelid: l_state.eyelid,
-
-                        gaze_pitch: l_state.pitch,
-                        gaze_yaw: l_state.yaw,
-
-                        timestamp: l_time,
+                }) => {
+                    let state = match eye {
+                        Eye::L => {
+                            l_eye.process(state, timestamp, &controls, l_eyelid, &mut blink_events)
+                        }
+                        Eye::R => {
+                            r_eye.process(state, timestamp, &controls, r_eyelid, &mut blink_events)
+                        }
                     };
+                    Some(EyesGazeState::Mono {
+                        eye,
+                        state,
+                        timestamp,
+                    })
                 }
+                None => None,
+            };
 
-                // Left eye is behind.
-                if l_time < r_time {
-                    // TODO: apply prediction.
-                }
-                // Right eye is behind.
-                if r_time < l_time {
-                    // TODO: apply prediction.
-                }
-
-                let timestamp = std::cmp::max(l_time, r_time);
-
-                let avg_pitch = (l_state.pitch + r_state.pitch) / 2.0;
-
-                let avg_yaw = (l_state.yaw + r_state.yaw) / 2.0;
-
-                // TODO: this is basically convergence distance, smooth it.
-                let yaw_diff = l_state.yaw - r_state.yaw;
-                // Slightly nudge the eyes together. Otherwise Steam Link refuses
-                // to use eye tracking from the `XR_FB_eye_tracking_social` extension.
-                // Probably tries to calculate convergence distance.
-                let yaw_diff = yaw_diff.max(0.05);
-
-                let l_yaw = avg_yaw + yaw_diff / 2.0;
-                let r_yaw = avg_yaw - yaw_diff / 2.0;
-
-                // let pitch = filter_pitch.filter_with_delta(eye.pitch, filter_secs);
-                // let yaw = filter_yaw.filter_with_delta(eye.yaw, filter_secs);
-                // let eyelid = filter_eyelid.filter_with_delta(eye.eyelid, filter_secs);
-
-                CombinedEyeGazeState {
-                    pitch: avg_pitch,
-                    l_yaw,
-                    r_yaw,
-                    l_eyelid: l_state.eyelid,
-                    r_eyelid: r_state.eyelid,
-
-                    gaze_pitch: avg_pitch,
-                    gaze_yaw: avg_yaw,
+            for event in blink_events.drain(..) {
+                // Nobody listening isn't an error, and a full queue drops the oldest event.
+                let _ = blink_tx.try_broadcast(event);
+            }
 
-                    timestamp,
-                }
+            // The clock only matters when no frames arrive.
+            let combined_gaze = CombinedEyeGazeState {
+                // Both eyes blink together, a missing camera shouldn't halve the rate.
+                blink_rate: l_eye.blink.rate().max(r_eye.blink.rate()),
+                ..combiner.combine(processed, SystemTime::now(), &settings)
             };
 
             // println!("{:#?}", combined_gaze);
 
-            tx.broadcast_direct(combined_gaze).await.unwrap();
+            if tx.broadcast_direct(combined_gaze).await.is_err() {
+                error!("Channel closed");
+                return;
+            }
         }
     })
 }
 
-// pub fn filter_eye(mut rx: Receiver<EyesGazeState>, tx: Sender<EyesGazeState>) -> JoinHandle<()> {
-//     tokio::spawn(async move {
-//         const PY_BETA: f32 = 0.3;
-//         const PY_FCMIN: f32 = 0.5;
-//         let mut filter_pitch = OneEuroFilter::new(0.0, PY_FCMIN, PY_FCMIN, PY_BETA);
-//         let mut filter_yaw = OneEuroFilter::new(0.0, PY_FCMIN, PY_FCMIN, PY_BETA);
-
-//         const EL_BETA: f32 = 1.0;
-//         const EL_FCMIN: f32 = 3.0;
-//         let mut filter_eyelid = OneEuroFilter::new(0.0, EL_FCMIN, EL_FCMIN, EL_BETA);
-
-//         let last_timestamp = SystemTime::now();
-
-//         while let Ok(eye) = rx.recv_direct().await {
-//             let filter_secs = eye
-//                 .timestamp
-//                 .duration_since(last_timestamp)
-//                 .unwrap()
-//                 .as_secs_f32();
-
-//             let pitch = filter_pitch.filter_with_delta(eye.pitch, filter_secs);
-//             let yaw = filter_yaw.filter_with_delta(eye.yaw, filter_secs);
-//             let eyelid = filter_eyelid.filter_with_delta(eye.eyelid, filter_secs);
-
-//             tx.broadcast_direct(EyesGazeState {
-//                 pitch,
-//                 yaw,
-//                 eyelid,
-//                 timestamp: eye.timestamp,
-//             })
-//             .await
-//             .unwrap();
-//         }
-//     })
-// }
-
 // pub fn merge_eyes(
 //     l_rx: Receiver<EyesGazeState>,
 //     r_rx: Receiver<EyesGazeState>,
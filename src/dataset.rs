@@ -0,0 +1,153 @@
+// Reading captures saved by the frame server: `<datetime>.json` with the label
+// sent by the client and `<datetime>_L.jpg`/`<datetime>_R.jpg` eye images.
+//
+// The frame server stores whatever the client sent, evaluation expects the label of each
+// captured eye under `l` and `r`, in degrees with positive pitch up and eyelid 0 closed
+// to 1 open, e.g. `{"l": {"pitch": 5.0, "yaw": -10.0, "eyelid": 1.0}, "r": [5.0, -8.0, 1.0]}`.
+
+use std::path::{Path, PathBuf};
+
+use image::RgbImage;
+
+use crate::structs::Eye;
+
+/// All eye images in `dir`, sorted by name. Right eyes are recognized by the `_R` suffix.
+pub fn load_eye_images(dir: &Path, limit: usize) -> std::io::Result<Vec<(PathBuf, bool)>> {
+    let mut paths = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| {
+            path.extension()
+                .and_then(|e| e.to_str())
+                .is_some_and(|e| matches!(e.to_ascii_lowercase().as_str(), "jpg" | "jpeg" | "png"))
+        })
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths.truncate(limit);
+
+    Ok(paths
+        .into_iter()
+        .map(|path| {
+            let is_left = !path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .is_some_and(|s| s.ends_with("_R"));
+            (path, is_left)
+        })
+        .collect())
+}
+
+pub fn decode_eye_image(path: &Path) -> image::ImageResult<RgbImage> {
+    // The frame server saves PNGs with a `.jpg` extension, so don't trust it.
+    Ok(image::ImageReader::open(path)?
+        .with_guessed_format()?
+        .decode()?
+        .into_rgb8())
+}
+
+/// Ground truth of a single eye, any of the values may be missing from the label.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EyeLabel {
+    pub pitch: Option<f32>,
+    pub yaw: Option<f32>,
+    pub eyelid: Option<f32>,
+}
+
+impl EyeLabel {
+    /// Accepts either `{"pitch": .., "yaw": .., "eyelid": ..}` with at least one of the
+    /// keys, or `[pitch, yaw, eyelid]`. Anything else is an error, not a missing label.
+    fn from_json(value: &serde_json::Value) -> Result<Self, String> {
+        let number = |key: &str, v: &serde_json::Value| {
+            v.as_f64()
+                .map(|v| v as f32)
+                .ok_or(format!("{key} isn't a number"))
+        };
+
+        match value {
+            serde_json::Value::Object(map) => {
+                let mut label = EyeLabel::default();
+                for (key, v) in map {
+                    match key.as_str() {
+                        "pitch" => label.pitch = Some(number(key, v)?),
+                        "yaw" => label.yaw = Some(number(key, v)?),
+                        "eyelid" => label.eyelid = Some(number(key, v)?),
+                        _ => return Err(format!("unknown key {key:?}")),
+                    }
+                }
+                if map.is_empty() {
+                    return Err("empty label".to_string());
+                }
+                Ok(label)
+            }
+            serde_json::Value::Array(values) => match values.as_slice() {
+                [pitch, yaw, eyelid] => Ok(EyeLabel {
+                    pitch: Some(number("pitch", pitch)?),
+                    yaw: Some(number("yaw", yaw)?),
+                    eyelid: Some(number("eyelid", eyelid)?),
+                }),
+                _ => Err(format!(
+                    "expected [pitch, yaw, eyelid], got {} values",
+                    values.len()
+                )),
+            },
+            _ => Err(format!("expected an object or an array, got {value}")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LabeledEyeImage {
+    pub image_path: PathBuf,
+    pub eye: Eye,
+    pub label: EyeLabel,
+}
+
+/// Pairs every label file in `dir` with the eye images captured for it.
+/// Fails on the first label that doesn't follow the schema or has no image, with the
+/// file and the reason in the error.
+pub fn load_labeled_captures(dir: &Path) -> Result<Vec<LabeledEyeImage>, String> {
+    let mut label_paths = std::fs::read_dir(dir)
+        .map_err(|err| format!("failed to read {}: {err}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|e| e == "json"))
+        .collect::<Vec<_>>();
+    label_paths.sort();
+
+    let mut captures = Vec::new();
+
+    for label_path in label_paths {
+        let error = |reason: String| format!("{}: {reason}", label_path.display());
+
+        let json = std::fs::read_to_string(&label_path).map_err(|err| error(err.to_string()))?;
+        let json = serde_json::from_str::<serde_json::Value>(&json)
+            .map_err(|err| error(format!("invalid JSON: {err}")))?;
+        let stem = label_path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or_else(|| error("file name isn't valid UTF-8".to_string()))?;
+
+        if json.get("l").is_none() && json.get("r").is_none() {
+            return Err(error("no \"l\" or \"r\" label".to_string()));
+        }
+
+        for (key, eye, letter) in [("l", Eye::L, 'L'), ("r", Eye::R, 'R')] {
+            let Some(value) = json.get(key) else {
+                continue;
+            };
+            let label =
+                EyeLabel::from_json(value).map_err(|reason| error(format!("{key:?}: {reason}")))?;
+
+            let image_path = dir.join(format!("{stem}_{letter}.jpg"));
+            if !image_path.exists() {
+                return Err(error(format!("missing image {}", image_path.display())));
+            }
+
+            captures.push(LabeledEyeImage {
+                image_path,
+                eye,
+                label,
+            });
+        }
+    }
+
+    Ok(captures)
+}
@@ -2,20 +2,29 @@ use crate::camera_dispatcher::{
     MonoCameraDispatcher, MonoEyeCameraDispatcher, StereoEyesCameraDispatcher,
 };
 use crate::camera_manager;
+use crate::control_server::{ControlContext, start_control_server};
 use crate::frame_server::start_frame_server;
 
 #[cfg(feature = "inference")]
-use crate::data_processing::process_gaze;
+use crate::benchmark::{BenchmarkArgs, run_benchmark};
 #[cfg(feature = "inference")]
-use crate::inference::eye_inference;
+use crate::data_processing::{GazeControls, process_gaze};
 #[cfg(feature = "inference")]
-use crate::osc_sender::start_osc_sender;
+use crate::evaluate::{EvaluateArgs, run_evaluation};
+#[cfg(feature = "inference")]
+use crate::eye_movements::classify_movements;
+#[cfg(feature = "inference")]
+use crate::inference::{ExecutionProviderKind, eye_inference};
+#[cfg(feature = "inference")]
+use crate::osc_receiver::start_osc_receiver;
+#[cfg(feature = "inference")]
+use crate::osc_sender::{OscDestination, start_osc_sender};
 
 use crate::structs::Eye;
 #[cfg(feature = "gui")]
 use crate::window_desktop::start_ui;
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use futures::future::try_join_all;
 use tokio::task::JoinHandle;
 
@@ -44,11 +53,19 @@ struct Args {
     #[arg(short = 'I')]
     inference: bool,
 
-    /// OSC output address
+    /// OSC output address, repeat for more destinations. Optionally followed by the
+    /// protocols to send, e.g. `localhost:9000=native,eyes-closed`, out of native,
+    /// eyes-closed, vrcft, confidence and focus
+    #[cfg(feature = "inference")]
     #[arg(short = 'o', default_value = "localhost:9000")]
-    osc_out_address: String,
+    osc_destinations: Vec<OscDestination>,
+
+    /// OSC input address, for remote actions like recentering. Localhost only by default,
+    /// use 0.0.0.0 to accept them from the LAN
+    #[arg(short = 'i', default_value = "127.0.0.1:9001")]
+    osc_in_address: String,
 
-    /// Path to the ONNX model
+    /// Path to the ONNX model, reloaded automatically when the file changes
     #[arg(short = 'm', default_value = "./model.onnx")]
     model_path: String,
 
@@ -56,19 +73,77 @@ struct Args {
     #[arg(short = 't', default_value_t = 1)]
     threads_per_eye: usize,
 
+    /// ONNX Runtime execution provider, falls back to CPU if unavailable
+    #[cfg(feature = "inference")]
+    #[arg(long = "ep", value_enum, default_value_t = ExecutionProviderKind::Cpu)]
+    execution_provider: ExecutionProviderKind,
+
+    /// Number of threads for running independent graph nodes, needs --parallel
+    #[arg(long, default_value_t = 1)]
+    inter_threads: usize,
+
+    /// Run independent graph nodes in parallel
+    #[arg(long)]
+    parallel: bool,
+
+    /// Disable the CPU memory arena
+    #[arg(long)]
+    no_arena: bool,
+
+    /// Disable inference thread spinning, lowers CPU usage at the cost of latency
+    #[arg(long)]
+    no_spinning: bool,
+
+    /// Accept control socket connections from other hosts. Unauthenticated, anyone on
+    /// the network can then change the model and where the gaze data is sent
+    #[arg(long)]
+    control_lan: bool,
+
     /// Headless mode, no GUI
     #[arg(short = 'H')]
     headless: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Benchmark the inference pipeline on recorded eye images
+    #[cfg(feature = "inference")]
+    Benchmark(BenchmarkArgs),
+
+    /// Measure the estimator error on labeled frame server captures
+    #[cfg(feature = "inference")]
+    Evaluate(EvaluateArgs),
 }
 
 pub async fn desktop_main() {
+    let mut args = Args::parse();
+
+    match args.command.take() {
+        #[cfg(feature = "inference")]
+        Some(Command::Benchmark(benchmark_args)) => {
+            tokio::task::spawn_blocking(move || run_benchmark(benchmark_args))
+                .await
+                .unwrap();
+            return;
+        }
+        #[cfg(feature = "inference")]
+        Some(Command::Evaluate(evaluate_args)) => {
+            tokio::task::spawn_blocking(move || run_evaluation(evaluate_args))
+                .await
+                .unwrap();
+            return;
+        }
+        None => {}
+    }
+
     nokhwa::query(nokhwa::utils::ApiBackend::Auto)
         .unwrap()
         .iter()
         .for_each(|cam| println!("{cam:#?}"));
 
-    let args = Args::parse();
-
     let app = App::new();
 
     let tasks = start_desktop_tasks(&args, &app);
@@ -134,29 +209,68 @@ fn start_desktop_tasks(args: &Args, app: &App) -> Vec<JoinHandle<()>> {
 
     tasks.push(start_frame_server(app.eyes_cam_rx.clone()));
 
+    // Runtime control
+
+    tasks.push(start_control_server(
+        ControlContext::new(app),
+        args.control_lan,
+    ));
+
     // Inference, process the data, output OSC
 
     if args.inference {
         #[cfg(feature = "inference")]
         {
+            app.inference_control.update_settings(|settings| {
+                settings.model_path = args.model_path.clone().into();
+                settings.threads_per_eye = args.threads_per_eye;
+                settings.execution_provider = args.execution_provider;
+                settings.inter_threads = args.inter_threads;
+                settings.parallel_execution = args.parallel;
+                settings.memory_arena = !args.no_arena;
+                settings.intra_op_spinning = !args.no_spinning;
+                settings.inter_op_spinning = !args.no_spinning;
+            });
+
             tasks.push(eye_inference(
                 app.eyes_cam_rx.activate_cloned(),
                 app.raw_eyes_tx.clone(),
-                &args.model_path,
-                args.threads_per_eye,
+                app.model_input_tx.clone(),
+                app.inference_control.clone(),
             ));
             // Filter
 
             tasks.push(process_gaze(
                 app.raw_eyes_rx.activate_cloned(),
                 app.combined_eyes_tx.clone(),
+                app.blink_tx.clone(),
+                GazeControls::new(app),
+            ));
+
+            // Eye movements
+
+            tasks.push(classify_movements(
+                app.combined_eyes_rx.activate_cloned(),
+                app.movement_tx.clone(),
+                app.movement_control.clone(),
             ));
 
             // OSC sender
 
+            app.osc_control
+                .set_destinations(args.osc_destinations.clone());
+
             tasks.push(start_osc_sender(
                 app.combined_eyes_rx.activate_cloned(),
-                args.osc_out_address.clone(),
+                app.osc_control.clone(),
+                app.output_profile_control.clone(),
+            ));
+
+            // OSC receiver
+
+            tasks.push(start_osc_receiver(
+                args.osc_in_address.clone(),
+                app.recenter_control.clone(),
             ));
         }
 
@@ -174,6 +288,32 @@ fn start_desktop_tasks(args: &Args, app: &App) -> Vec<JoinHandle<()>> {
                 f_rx: app.f_cam_rx.activate_cloned(),
                 raw_eyes_rx: app.raw_eyes_rx.activate_cloned(),
                 combined_eyes_rx: app.combined_eyes_rx.activate_cloned(),
+                #[cfg(feature = "inference")]
+                inference_control: app.inference_control.clone(),
+                #[cfg(feature = "inference")]
+                model_input_rx: app.model_input_rx.activate_cloned(),
+                #[cfg(feature = "inference")]
+                filter_control: app.filter_control.clone(),
+                #[cfg(feature = "inference")]
+                calibration_control: app.calibration_control.clone(),
+                #[cfg(feature = "inference")]
+                recenter_control: app.recenter_control.clone(),
+                #[cfg(feature = "inference")]
+                blink_rx: app.blink_rx.activate_cloned(),
+                #[cfg(feature = "inference")]
+                movement_control: app.movement_control.clone(),
+                #[cfg(feature = "inference")]
+                movement_rx: app.movement_rx.activate_cloned(),
+                #[cfg(feature = "inference")]
+                vergence_control: app.vergence_control.clone(),
+                #[cfg(feature = "inference")]
+                tracking_control: app.tracking_control.clone(),
+                #[cfg(feature = "inference")]
+                post_process_control: app.post_process_control.clone(),
+                #[cfg(feature = "inference")]
+                output_profile_control: app.output_profile_control.clone(),
+                #[cfg(feature = "inference")]
+                osc_control: app.osc_control.clone(),
             }));
         }
 
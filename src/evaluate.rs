@@ -0,0 +1,273 @@
+use std::io::Write;
+use std::path::PathBuf;
+
+use image::GenericImageView;
+
+use crate::dataset::{EyeLabel, decode_eye_image, load_labeled_captures};
+use crate::inference::{
+    ExecutionProviderKind, EyeHistory, InferenceSettings, ModelLayout, crop_eye, load_session,
+    model_input_image, resize_eye, run_model,
+};
+use crate::preprocessing::{Equalization, PreprocessingSettings};
+use crate::segmentation::SegmentationMapping;
+use crate::structs::{Eye, EyeGazeState};
+
+#[derive(clap::Args, Debug)]
+pub struct EvaluateArgs {
+    /// Directory with labeled frame server captures
+    #[arg(default_value = "./images")]
+    images_dir: PathBuf,
+
+    /// Path to the ONNX model
+    #[arg(short = 'm', default_value = "./model.onnx")]
+    model_path: PathBuf,
+
+    /// Where to write the per-sample results
+    #[arg(short = 'o', default_value = "./evaluation.csv")]
+    csv_path: PathBuf,
+
+    /// Number of threads to use for inference
+    #[arg(short = 't', default_value_t = 1)]
+    threads: usize,
+
+    /// ONNX Runtime execution provider, falls back to CPU if unavailable
+    #[arg(long = "ep", value_enum, default_value_t = ExecutionProviderKind::Cpu)]
+    execution_provider: ExecutionProviderKind,
+
+    #[command(flatten)]
+    preprocessing: PreprocessingArgs,
+}
+
+/// Model input preprocessing, everything off by default like in the app.
+#[derive(clap::Args, Debug)]
+struct PreprocessingArgs {
+    /// Correct the vignetting with this strength
+    #[arg(long)]
+    vignette: Option<f32>,
+
+    /// Denoise with a Gaussian blur of this sigma
+    #[arg(long)]
+    denoise: Option<f32>,
+
+    /// Stretch the 1st..99th percentile to the full range
+    #[arg(long)]
+    auto_gain: bool,
+
+    /// Histogram equalization
+    #[arg(long, value_enum, default_value_t = Equalization::None)]
+    equalization: Equalization,
+
+    /// CLAHE contrast limit, relative to the average histogram bin
+    #[arg(long, default_value_t = PreprocessingSettings::default().clahe_clip_limit)]
+    clahe_clip_limit: f32,
+
+    /// CLAHE tiles along each side of the image
+    #[arg(long, default_value_t = PreprocessingSettings::default().clahe_tiles)]
+    clahe_tiles: u32,
+
+    /// Gamma correction with this gamma, below 1 brightens
+    #[arg(long)]
+    gamma: Option<f32>,
+}
+
+impl PreprocessingArgs {
+    fn settings(&self) -> PreprocessingSettings {
+        let default = PreprocessingSettings::default();
+        PreprocessingSettings {
+            vignette_correction: self.vignette.is_some(),
+            vignette_strength: self.vignette.unwrap_or(default.vignette_strength),
+            denoise: self.denoise.is_some(),
+            denoise_sigma: self.denoise.unwrap_or(default.denoise_sigma),
+            auto_gain: self.auto_gain,
+            equalization: self.equalization,
+            clahe_clip_limit: self.clahe_clip_limit,
+            clahe_tiles: self.clahe_tiles,
+            gamma_correction: self.gamma.is_some(),
+            gamma: self.gamma.unwrap_or(default.gamma),
+        }
+    }
+}
+
+/// Absolute errors of a single value.
+#[derive(Default)]
+struct ErrorStats(Vec<f32>);
+
+impl ErrorStats {
+    fn push(&mut self, label: Option<f32>, predicted: f32) -> Option<f32> {
+        let error = (predicted - label?).abs();
+        self.0.push(error);
+        Some(error)
+    }
+
+    fn summary(&mut self) -> String {
+        if self.0.is_empty() {
+            return "no labels".to_string();
+        }
+
+        self.0.sort_by(f32::total_cmp);
+        let percentile = |p: f32| self.0[((self.0.len() - 1) as f32 * p).round() as usize];
+        let mean = self.0.iter().sum::<f32>() / self.0.len() as f32;
+
+        format!(
+            "mean {:7.3}, median {:7.3}, p95 {:7.3} (n = {})",
+            mean,
+            percentile(0.5),
+            percentile(0.95),
+            self.0.len()
+        )
+    }
+}
+
+#[derive(Default)]
+struct EyeStats {
+    pitch: ErrorStats,
+    yaw: ErrorStats,
+    eyelid: ErrorStats,
+}
+
+fn csv_value(value: Option<f32>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
+
+/// Quoted, so commas and quotes in paths don't break the columns.
+fn csv_text(text: &str) -> String {
+    format!("\"{}\"", text.replace('"', "\"\""))
+}
+
+pub fn run_evaluation(args: EvaluateArgs) {
+    let captures = match load_labeled_captures(&args.images_dir) {
+        Ok(captures) => captures,
+        Err(err) => {
+            eprintln!("Invalid captures, {err}");
+            std::process::exit(1);
+        }
+    };
+
+    if captures.is_empty() {
+        eprintln!("No labeled captures found in {}", args.images_dir.display());
+        std::process::exit(1);
+    }
+
+    let inference_settings = InferenceSettings {
+        model_path: args.model_path.clone(),
+        threads_per_eye: args.threads,
+        execution_provider: args.execution_provider,
+        ..Default::default()
+    };
+    let preprocessing = args.preprocessing.settings();
+
+    // The fallback reason is already logged.
+    let mut model = match load_session(&inference_settings) {
+        Ok((model, _)) => model,
+        Err(err) => {
+            eprintln!("Failed to load {}: {err}", args.model_path.display());
+            std::process::exit(1);
+        }
+    };
+
+    let mut csv = match std::fs::File::create(&args.csv_path) {
+        Ok(file) => std::io::BufWriter::new(file),
+        Err(err) => {
+            eprintln!("Failed to create {}: {err}", args.csv_path.display());
+            std::process::exit(1);
+        }
+    };
+    writeln!(
+        csv,
+        "image,eye,label_pitch,pred_pitch,err_pitch,label_yaw,pred_yaw,err_yaw,label_eyelid,pred_eyelid,err_eyelid"
+    )
+    .unwrap();
+
+    // Labeled captures aren't consecutive frames, so every one starts a fresh history.
+    let layout = ModelLayout::from_session(&model);
+    let mut l_history = EyeHistory::default();
+    let mut r_history = EyeHistory::default();
+    let mapping = SegmentationMapping::default();
+
+    let mut l_stats = EyeStats::default();
+    let mut r_stats = EyeStats::default();
+    let mut evaluated = 0;
+
+    for capture in &captures {
+        let image = match decode_eye_image(&capture.image_path) {
+            Ok(image) => image,
+            Err(err) => {
+                eprintln!("Skipping {}: {err}", capture.image_path.display());
+                continue;
+            }
+        };
+
+        let is_left = capture.eye == Eye::L;
+        let history = if is_left {
+            &mut l_history
+        } else {
+            &mut r_history
+        };
+        let (image, estimates) = model_input_image(
+            &resize_eye(&crop_eye(
+                image.view(0, 0, image.width(), image.height()),
+                is_left,
+            )),
+            &preprocessing,
+        );
+        history.reset();
+        let input = history.input_tensor(&layout, image);
+        let predicted: EyeGazeState = match run_model(
+            &mut model, &layout, history, &mapping, &input, estimates, is_left,
+        ) {
+            Ok(output) => output.state,
+            Err(err) => {
+                eprintln!(
+                    "Inference failed on {}: {err}",
+                    capture.image_path.display()
+                );
+                continue;
+            }
+        };
+
+        evaluated += 1;
+
+        let stats = if is_left { &mut l_stats } else { &mut r_stats };
+        let EyeLabel { pitch, yaw, eyelid } = capture.label;
+
+        let pitch_error = stats.pitch.push(pitch, predicted.pitch);
+        let yaw_error = stats.yaw.push(yaw, predicted.yaw);
+        let eyelid_error = stats.eyelid.push(eyelid, predicted.eyelid);
+
+        writeln!(
+            csv,
+            "{},{:?},{},{},{},{},{},{},{},{},{}",
+            csv_text(&capture.image_path.display().to_string()),
+            capture.eye,
+            csv_value(pitch),
+            predicted.pitch,
+            csv_value(pitch_error),
+            csv_value(yaw),
+            predicted.yaw,
+            csv_value(yaw_error),
+            csv_value(eyelid),
+            predicted.eyelid,
+            csv_value(eyelid_error),
+        )
+        .unwrap();
+    }
+
+    csv.flush().unwrap();
+
+    println!(
+        "Evaluated {} on {evaluated} of {} eye images, absolute errors:",
+        args.model_path.display(),
+        captures.len()
+    );
+    println!(
+        "{:?} execution provider, {} thread(s), preprocessing: {preprocessing:?}",
+        args.execution_provider, args.threads
+    );
+    for (name, stats) in [("Left", &mut l_stats), ("Right", &mut r_stats)] {
+        println!("{name} eye");
+        println!("  pitch:  {}", stats.pitch.summary());
+        println!("  yaw:    {}", stats.yaw.summary());
+        println!("  eyelid: {}", stats.eyelid.summary());
+    }
+    println!("Per-sample results written to {}", args.csv_path.display());
+}
@@ -0,0 +1,262 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use async_broadcast::{Receiver, RecvError, Sender};
+use log::{error, warn};
+use tokio::task::JoinHandle;
+
+use crate::structs::{CombinedEyeGazeState, Timestamp};
+
+/// Samples further apart than this end the current movement without an event for the gap.
+const MAX_SAMPLE_GAP: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MovementKind {
+    Fixation,
+    Saccade,
+    SmoothPursuit,
+}
+
+/// A finished eye movement, sent when the next one starts.
+#[derive(Clone, Copy, Debug)]
+pub struct MovementEvent {
+    pub kind: MovementKind,
+    pub start: Timestamp,
+    pub duration: Duration,
+    /// Mean gaze pitch and yaw over the movement, degrees.
+    pub centroid: (f32, f32),
+    /// Angle between the first and last gaze, degrees.
+    pub amplitude: f32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MovementSettings {
+    /// I-VT, samples faster than this are saccades, deg/s. Also what restarts the gaze
+    /// filters, see `FilterSettings::restart_on_saccades`.
+    pub saccade_velocity: f32,
+    /// I-DT, slower samples within this dispersion over the window are fixations,
+    /// the rest is smooth pursuit, degrees.
+    pub fixation_dispersion: f32,
+    /// Duration the dispersion is measured over, also the shortest fixation.
+    pub fixation_window: Duration,
+}
+
+impl Default for MovementSettings {
+    fn default() -> Self {
+        Self {
+            saccade_velocity: 100.0,
+            fixation_dispersion: 1.5,
+            fixation_window: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Classifier thresholds shared with the UI.
+#[derive(Debug, Default)]
+pub struct MovementControl {
+    settings: Mutex<MovementSettings>,
+}
+
+impl MovementControl {
+    pub fn settings(&self) -> MovementSettings {
+        *self.settings.lock().unwrap()
+    }
+
+    pub fn set_settings(&self, settings: MovementSettings) {
+        *self.settings.lock().unwrap() = settings;
+    }
+}
+
+fn angle_between(a: (f32, f32), b: (f32, f32)) -> f32 {
+    (a.0 - b.0).hypot(a.1 - b.1)
+}
+
+/// I-VT part of the classifier. The saccade-aware filters use it too, so both agree on
+/// what a saccade is.
+#[derive(Debug, Default)]
+pub struct SaccadeDetector {
+    last: Option<(Timestamp, (f32, f32))>,
+}
+
+impl SaccadeDetector {
+    /// Whether the gaze moved faster than `settings.saccade_velocity` since the previous
+    /// sample, `None` without a previous sample within `MAX_SAMPLE_GAP`.
+    pub fn update(
+        &mut self,
+        timestamp: Timestamp,
+        gaze: (f32, f32),
+        settings: &MovementSettings,
+    ) -> Option<bool> {
+        let velocity = self.last.and_then(|(last_timestamp, last_gaze)| {
+            let dt = timestamp.duration_since(last_timestamp).unwrap_or_default();
+            (dt <= MAX_SAMPLE_GAP)
+                .then(|| angle_between(gaze, last_gaze) / dt.as_secs_f32().max(0.001))
+        });
+        self.last = Some((timestamp, gaze));
+        velocity.map(|velocity| velocity > settings.saccade_velocity)
+    }
+
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+/// Consecutive samples of the same kind.
+struct Segment {
+    kind: MovementKind,
+    start: Timestamp,
+    end: Timestamp,
+    first: (f32, f32),
+    last: (f32, f32),
+    sum: (f32, f32),
+    count: usize,
+}
+
+impl Segment {
+    fn new(kind: MovementKind, timestamp: Timestamp, gaze: (f32, f32)) -> Self {
+        Self {
+            kind,
+            start: timestamp,
+            end: timestamp,
+            first: gaze,
+            last: gaze,
+            sum: gaze,
+            count: 1,
+        }
+    }
+
+    fn push(&mut self, timestamp: Timestamp, gaze: (f32, f32)) {
+        self.end = timestamp;
+        self.last = gaze;
+        self.sum.0 += gaze.0;
+        self.sum.1 += gaze.1;
+        self.count += 1;
+    }
+
+    fn event(&self) -> MovementEvent {
+        MovementEvent {
+            kind: self.kind,
+            start: self.start,
+            duration: self.end.duration_since(self.start).unwrap_or_default(),
+            centroid: (
+                self.sum.0 / self.count as f32,
+                self.sum.1 / self.count as f32,
+            ),
+            amplitude: angle_between(self.first, self.last),
+        }
+    }
+}
+
+/// Hybrid I-VT/I-DT classifier of the combined gaze.
+#[derive(Default)]
+struct MovementClassifier {
+    saccades: SaccadeDetector,
+    // Recent non-saccade samples, for the dispersion.
+    window: Vec<(Timestamp, (f32, f32))>,
+    segment: Option<Segment>,
+}
+
+impl MovementClassifier {
+    fn dispersion(&self) -> f32 {
+        let (mut min, mut max) = ((f32::MAX, f32::MAX), (f32::MIN, f32::MIN));
+        for (_, (pitch, yaw)) in &self.window {
+            min = (min.0.min(*pitch), min.1.min(*yaw));
+            max = (max.0.max(*pitch), max.1.max(*yaw));
+        }
+        (max.0 - min.0) + (max.1 - min.1)
+    }
+
+    /// Ends the current movement, e.g. when tracking was lost.
+    fn flush(&mut self) -> Option<MovementEvent> {
+        self.saccades.reset();
+        self.window.clear();
+        self.segment.take().map(|segment| segment.event())
+    }
+
+    fn update(
+        &mut self,
+        timestamp: Timestamp,
+        gaze: (f32, f32),
+        settings: &MovementSettings,
+    ) -> Option<MovementEvent> {
+        let mut finished = None;
+
+        let saccade = self.saccades.update(timestamp, gaze, settings);
+        if saccade.is_none() {
+            // First sample after a gap, the previous movement ends without it.
+            self.window.clear();
+            finished = self.segment.take().map(|segment| segment.event());
+        }
+
+        let kind = if saccade == Some(true) {
+            self.window.clear();
+            MovementKind::Saccade
+        } else {
+            self.window.push((timestamp, gaze));
+            self.window.retain(|(t, _)| {
+                timestamp.duration_since(*t).unwrap_or_default() <= settings.fixation_window
+            });
+
+            if self.dispersion() <= settings.fixation_dispersion {
+                MovementKind::Fixation
+            } else {
+                MovementKind::SmoothPursuit
+            }
+        };
+
+        match self.segment.as_mut() {
+            Some(segment) if segment.kind == kind => segment.push(timestamp, gaze),
+            _ => {
+                // After a gap there is no previous movement left to finish.
+                if let Some(previous) = self.segment.replace(Segment::new(kind, timestamp, gaze)) {
+                    finished = Some(previous.event());
+                }
+            }
+        }
+
+        finished
+    }
+}
+
+pub fn classify_movements(
+    mut rx: Receiver<CombinedEyeGazeState>,
+    tx: Sender<MovementEvent>,
+    control: Arc<MovementControl>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut classifier = MovementClassifier::default();
+
+        loop {
+            let gaze = loop {
+                match rx.recv_direct().await {
+                    Ok(gaze) => break gaze,
+                    Err(e) => match e {
+                        RecvError::Overflowed(skipped) => {
+                            warn!("Skipped {skipped} gaze samples");
+                            continue;
+                        }
+                        RecvError::Closed => {
+                            error!("Channel closed");
+                            return;
+                        }
+                    },
+                }
+            };
+
+            let event = if gaze.l_valid || gaze.r_valid {
+                classifier.update(
+                    gaze.timestamp,
+                    (gaze.gaze_pitch, gaze.gaze_yaw),
+                    &control.settings(),
+                )
+            } else {
+                classifier.flush()
+            };
+
+            if let Some(event) = event {
+                // Nobody listening isn't an error, and a full queue drops the oldest event.
+                let _ = tx.try_broadcast(event);
+            }
+        }
+    })
+}
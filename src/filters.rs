@@ -0,0 +1,261 @@
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use one_euro_rs::OneEuroFilter;
+
+/// Guards against zero or negative delta time from frames with the same timestamp.
+const MIN_DELTA_SECS: f32 = 0.001;
+
+/// Window sizes above this are clamped, the filters are meant for a few frames of history.
+pub const MAX_WINDOW: usize = 31;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FilterKind {
+    /// Adaptive low pass, smooth when slow and responsive when fast.
+    OneEuro {
+        min_cutoff: f32,
+        d_cutoff: f32,
+        beta: f32,
+    },
+    /// Median of the last `window` samples.
+    Median { window: usize },
+    /// Replaces samples further than `threshold` scaled MADs from the window median.
+    Hampel { window: usize, threshold: f32 },
+    /// Exponential moving average with a time constant in seconds.
+    Ema { time_constant: f32 },
+    /// Ignores changes smaller than `width`, the output trails the input by up to `width`.
+    Deadzone { width: f32 },
+}
+
+impl FilterKind {
+    /// Defaults for every kind, in the order shown in the UI.
+    pub const ALL: [FilterKind; 5] = [
+        FilterKind::OneEuro {
+            min_cutoff: 0.5,
+            d_cutoff: 0.5,
+            beta: 0.3,
+        },
+        FilterKind::Median { window: 5 },
+        FilterKind::Hampel {
+            window: 7,
+            threshold: 3.0,
+        },
+        FilterKind::Ema {
+            time_constant: 0.05,
+        },
+        FilterKind::Deadzone { width: 0.5 },
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterKind::OneEuro { .. } => "One Euro",
+            FilterKind::Median { .. } => "Median",
+            FilterKind::Hampel { .. } => "Hampel",
+            FilterKind::Ema { .. } => "EMA",
+            FilterKind::Deadzone { .. } => "Deadzone",
+        }
+    }
+}
+
+/// Filter chains for each channel, applied in order to both eyes separately.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FilterSettings {
+    pub pitch: Vec<FilterKind>,
+    pub yaw: Vec<FilterKind>,
+    pub eyelid: Vec<FilterKind>,
+    /// Gaze filters restart from the raw value on saccades, so they aren't delayed by the
+    /// smoothing. Saccades are detected with `MovementSettings::saccade_velocity`.
+    pub restart_on_saccades: bool,
+}
+
+impl Default for FilterSettings {
+    fn default() -> Self {
+        let gaze = FilterKind::OneEuro {
+            min_cutoff: 0.5,
+            d_cutoff: 0.5,
+            beta: 0.3,
+        };
+        let eyelid = FilterKind::OneEuro {
+            min_cutoff: 3.0,
+            d_cutoff: 3.0,
+            beta: 1.0,
+        };
+
+        Self {
+            pitch: vec![gaze],
+            yaw: vec![gaze],
+            eyelid: vec![eyelid],
+            restart_on_saccades: false,
+        }
+    }
+}
+
+/// Filter settings shared with the UI, picked up by the gaze processing on the next sample.
+#[derive(Debug, Default)]
+pub struct FilterControl {
+    settings: Mutex<FilterSettings>,
+    generation: AtomicU64,
+}
+
+impl FilterControl {
+    pub fn settings(&self) -> FilterSettings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    pub fn update_settings(&self, f: impl FnOnce(&mut FilterSettings)) {
+        f(&mut self.settings.lock().unwrap());
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Changes every time the settings do.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+}
+
+/// Upper median, `values` must not be empty.
+pub fn median(values: impl IntoIterator<Item = f32>) -> f32 {
+    let mut values = values.into_iter().collect::<Vec<_>>();
+    values.sort_by(f32::total_cmp);
+    values[values.len() / 2]
+}
+
+enum FilterState {
+    /// Also keeps the last output, to restart from there when the parameters change.
+    OneEuro(OneEuroFilter, f32),
+    Window(VecDeque<f32>),
+    Last(f32),
+}
+
+struct Filter {
+    kind: FilterKind,
+    // Created on the first sample, so the filters start from the actual value.
+    state: Option<FilterState>,
+}
+
+impl Filter {
+    /// Takes over new parameters of the same kind without losing the history.
+    fn set_kind(&mut self, kind: FilterKind) {
+        if kind == self.kind {
+            return;
+        }
+        if let (
+            FilterKind::OneEuro {
+                min_cutoff,
+                d_cutoff,
+                beta,
+            },
+            Some(FilterState::OneEuro(filter, last)),
+        ) = (kind, &mut self.state)
+        {
+            *filter = OneEuroFilter::new(*last, min_cutoff, d_cutoff, beta);
+        }
+        self.kind = kind;
+    }
+
+    fn apply(&mut self, value: f32, delta_secs: f32) -> f32 {
+        let state = self.state.get_or_insert_with(|| match self.kind {
+            FilterKind::OneEuro {
+                min_cutoff,
+                d_cutoff,
+                beta,
+            } => FilterState::OneEuro(OneEuroFilter::new(value, min_cutoff, d_cutoff, beta), value),
+            FilterKind::Median { .. } | FilterKind::Hampel { .. } => {
+                FilterState::Window(VecDeque::new())
+            }
+            FilterKind::Ema { .. } | FilterKind::Deadzone { .. } => FilterState::Last(value),
+        });
+
+        match (self.kind, state) {
+            (FilterKind::OneEuro { .. }, FilterState::OneEuro(filter, last)) => {
+                *last = filter.filter_with_delta(value, delta_secs);
+                *last
+            }
+            (FilterKind::Median { window }, FilterState::Window(history)) => {
+                history.push_back(value);
+                while history.len() > window.clamp(1, MAX_WINDOW) {
+                    history.pop_front();
+                }
+                median(history.iter().copied())
+            }
+            (FilterKind::Hampel { window, threshold }, FilterState::Window(history)) => {
+                history.push_back(value);
+                while history.len() > window.clamp(1, MAX_WINDOW) {
+                    history.pop_front();
+                }
+
+                // MAD scaled to match the standard deviation of normally distributed data.
+                const MAD_SCALE: f32 = 1.4826;
+                let window_median = median(history.iter().copied());
+                let mad = median(history.iter().map(|v| (v - window_median).abs())) * MAD_SCALE;
+
+                if (value - window_median).abs() > threshold * mad {
+                    window_median
+                } else {
+                    value
+                }
+            }
+            (FilterKind::Ema { time_constant }, FilterState::Last(last)) => {
+                let alpha = if time_constant > 0.0 {
+                    1.0 - (-delta_secs / time_constant).exp()
+                } else {
+                    1.0
+                };
+                *last += (value - *last) * alpha;
+                *last
+            }
+            (FilterKind::Deadzone { width }, FilterState::Last(last)) => {
+                let difference = value - *last;
+                if difference.abs() > width {
+                    *last = value - difference.signum() * width;
+                }
+                *last
+            }
+            _ => unreachable!("filter state doesn't match its kind"),
+        }
+    }
+}
+
+/// Chain of filters for a single value.
+#[derive(Default)]
+pub struct FilterChain {
+    filters: Vec<Filter>,
+}
+
+impl FilterChain {
+    /// Changed parameters are taken over in place. Only adding, removing or reordering
+    /// filters rebuilds the chain, which resets the filter states.
+    pub fn configure(&mut self, kinds: &[FilterKind]) {
+        let same_kinds =
+            self.filters.len() == kinds.len()
+                && self.filters.iter().zip(kinds).all(|(filter, kind)| {
+                    mem::discriminant(&filter.kind) == mem::discriminant(kind)
+                });
+
+        if same_kinds {
+            for (filter, &kind) in self.filters.iter_mut().zip(kinds) {
+                filter.set_kind(kind);
+            }
+        } else {
+            self.filters = kinds
+                .iter()
+                .map(|&kind| Filter { kind, state: None })
+                .collect();
+        }
+    }
+
+    pub fn apply(&mut self, value: f32, delta_secs: f32) -> f32 {
+        let delta_secs = delta_secs.max(MIN_DELTA_SECS);
+        self.filters
+            .iter_mut()
+            .fold(value, |value, filter| filter.apply(value, delta_secs))
+    }
+
+    pub fn reset(&mut self) {
+        for filter in &mut self.filters {
+            filter.state = None;
+        }
+    }
+}
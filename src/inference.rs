@@ -1,13 +1,31 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
 use async_broadcast::{Receiver, RecvError, Sender};
-use image::{DynamicImage, GenericImageView};
-use log::{error, warn};
+use image::{DynamicImage, GrayImage, Luma, RgbImage};
+use log::{error, info, warn};
+use ndarray::{Array4, ArrayD, IxDyn};
 use ort::{
-    session::{Session, builder::GraphOptimizationLevel},
+    execution_providers::{
+        CPUExecutionProvider, CUDAExecutionProvider, CoreMLExecutionProvider,
+        DirectMLExecutionProvider, ExecutionProvider, ExecutionProviderDispatch,
+        NNAPIExecutionProvider, XNNPACKExecutionProvider,
+    },
+    session::{
+        Session, SessionInputValue,
+        builder::{GraphOptimizationLevel, SessionBuilder},
+    },
     value::TensorRef,
 };
 use tokio::task::JoinHandle;
 
-use crate::structs::{Eye, EyeGazeState, EyesGazeState};
+use crate::preprocessing::{PreprocessingSettings, preprocess};
+use crate::segmentation::{EyeFeatures, SegmentationLayout, SegmentationMapping};
+use crate::structs::{Eye, EyeGazeState, EyesGazeState, Timestamp};
+use crate::validation::{FaultCounters, OutputLimits, OutputValidator};
 use crate::structs::{EyesFrame, EyesFrameType};
 
 pub const FRAME_CROP_X: u32 = 30;
@@ -17,36 +35,714 @@ pub const FRAME_CROP_H: u32 = 180;
 pub const FRAME_RESIZE_W: u32 = 64;
 pub const FRAME_RESIZE_H: u32 = 64;
 
+/// How often the model input is published for the UI.
+const MODEL_INPUT_DEBUG_INTERVAL: Duration = Duration::from_millis(100);
+
+/// After this many failed runs in a row the session is rebuilt.
+const MAX_CONSECUTIVE_RUN_ERRORS: u32 = 5;
+
+/// History and recurrent state of an eye are dropped after a gap this long.
+const HISTORY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Models with more than 3 outputs are expected to report confidence as the 4th value.
+const MODEL_CONFIDENCE_OUTPUT: usize = 3;
+
+/// Models with more than 4 outputs are expected to report pupil size as the 5th value.
+const MODEL_PUPIL_OUTPUT: usize = 4;
+
+/// Classical dark pupil measure for models that don't report pupil size.
+/// Returns the fraction of the model input that is close to the darkest pixel,
+/// which under IR illumination is dominated by the pupil.
+fn estimate_pupil_area(pixels: &[f32]) -> f32 {
+    // How far between the darkest and the mean intensity a pixel can be to count as pupil.
+    const PUPIL_THRESHOLD: f32 = 0.2;
+
+    if pixels.is_empty() {
+        return 0.0;
+    }
+
+    let count = pixels.len() as f32;
+    let mean = pixels.iter().sum::<f32>() / count;
+    let min = pixels.iter().copied().fold(f32::INFINITY, f32::min);
+    let threshold = min + (mean - min) * PUPIL_THRESHOLD;
+
+    pixels.iter().filter(|&&p| p <= threshold).count() as f32 / count
+}
+
+/// Rough quality estimate of the model input for models that don't report confidence.
+/// Penalizes under/overexposed, low contrast and clipped images, which is where
+/// the regressor output stops being reliable.
+fn estimate_image_confidence(pixels: &[f32]) -> f32 {
+    const DARK_LIMIT: f32 = 40.0;
+    const BRIGHT_LIMIT: f32 = 200.0;
+    const GOOD_STD_DEV: f32 = 40.0;
+    const CLIP_LOW: f32 = 5.0;
+    const CLIP_HIGH: f32 = 250.0;
+
+    if pixels.is_empty() {
+        return 0.0;
+    }
+
+    let count = pixels.len() as f32;
+    let mean = pixels.iter().sum::<f32>() / count;
+    let std_dev = (pixels.iter().map(|p| (p - mean).powi(2)).sum::<f32>() / count).sqrt();
+    let clipped = pixels
+        .iter()
+        .filter(|&&p| p <= CLIP_LOW || p >= CLIP_HIGH)
+        .count() as f32
+        / count;
+
+    let exposure = if mean < DARK_LIMIT {
+        mean / DARK_LIMIT
+    } else if mean > BRIGHT_LIMIT {
+        (255.0 - mean) / (255.0 - BRIGHT_LIMIT)
+    } else {
+        1.0
+    };
+    let contrast = std_dev / GOOD_STD_DEV;
+    let clipping = 1.0 - clipped * 2.0;
+
+    (exposure.clamp(0.0, 1.0) * contrast.clamp(0.0, 1.0) * clipping.clamp(0.0, 1.0))
+        .clamp(0.0, 1.0)
+}
+
+/// Heuristic estimates for models that don't report these values themselves.
+#[derive(Clone, Copy, Debug)]
+pub struct ImageEstimates {
+    pub confidence: f32,
+    pub pupil: f32,
+}
+
+impl ImageEstimates {
+    fn from_image(image: &GrayImage) -> Self {
+        let pixels = image.pixels().map(|p| p[0] as f32).collect::<Vec<_>>();
+        Self {
+            confidence: estimate_image_confidence(&pixels),
+            pupil: estimate_pupil_area(&pixels),
+        }
+    }
+}
+
+pub type EyeView<'a> = image::SubImage<&'a image::ImageBuffer<image::Rgb<u8>, Vec<u8>>>;
+
+/// Flips the right eye to look like a left one and crops the area the model was trained on.
+pub fn crop_eye(frame_view: EyeView, is_left: bool) -> RgbImage {
+    // TODO: make it grayscale for a bit less operations? But crashes for some reason so far.
+    // let mut frame_view = DynamicImage::ImageRgb8(frame_view.to_image()).grayscale();
+    let mut frame_view = DynamicImage::ImageRgb8(frame_view.to_image());
+
+    if !is_left {
+        frame_view = frame_view.fliph();
+    }
+
+    frame_view
+        .crop_imm(FRAME_CROP_X, FRAME_CROP_Y, FRAME_CROP_W, FRAME_CROP_H)
+        .into_rgb8()
+}
+
+/// Downscales the cropped eye to the model input size.
+pub fn resize_eye(cropped: &RgbImage) -> RgbImage {
+    image::imageops::resize(
+        cropped,
+        FRAME_RESIZE_W,
+        FRAME_RESIZE_H,
+        image::imageops::FilterType::Lanczos3,
+    )
+}
+
+/// Single channel image the model sees, the red channel with optional preprocessing.
+/// The estimates are taken before preprocessing, which would hide the exposure.
+pub fn model_input_image(
+    resized: &RgbImage,
+    preprocessing: &PreprocessingSettings,
+) -> (GrayImage, ImageEstimates) {
+    let mut image = GrayImage::from_fn(resized.width(), resized.height(), |x, y| {
+        Luma([resized.get_pixel(x, y)[0]])
+    });
+    let estimates = ImageEstimates::from_image(&image);
+    preprocess(&mut image, preprocessing);
+    (image, estimates)
+}
+
+/// Model input in NHWC layout.
+pub fn build_input_tensor(input: &GrayImage) -> Array4<f32> {
+    // Panics that the shape is wrong when using this.
+    // let array = ndarray::Array::from_vec(final_frame.into_vec());
+    Array4::from_shape_vec(
+        (1, input.height() as usize, input.width() as usize, 1),
+        input.pixels().map(|p| p[0] as f32).collect(),
+    )
+    .unwrap()
+}
+
+/// Converts the input tensor back into an image, to see exactly what the model sees.
+/// For frame stacks this is the newest frame.
+pub fn tensor_image(input: &Array4<f32>) -> GrayImage {
+    let (_, h, w, frames) = input.dim();
+    GrayImage::from_fn(w as u32, h as u32, |x, y| {
+        Luma([input[[0, y as usize, x as usize, frames - 1]].clamp(0.0, 255.0) as u8])
+    })
+}
+
+/// A recurrent state input of the model, fed from the paired output of the previous run.
+#[derive(Clone, Debug)]
+pub struct StateLayout {
+    pub input: String,
+    pub output: String,
+    /// Shape of the initial zero state, dynamic dimensions are taken as 1.
+    pub shape: Vec<usize>,
+}
+
+/// How to feed the model, read from its custom metadata:
+/// - `image_input`: name of the image input, defaults to the first non state input.
+/// - `gaze_output`: name of the gaze output, defaults to the first non state output.
+/// - `frame_history`: number of frames stacked on the last image input dimension, oldest
+///   first. Defaults to 1.
+/// - `state_inputs`/`state_outputs`: comma separated names of the recurrent state, paired
+///   by position.
+/// - `segmentation_classes`: makes the gaze output a segmentation mask, see
+///   `SegmentationLayout`.
+#[derive(Clone, Debug)]
+pub struct ModelLayout {
+    pub image_input: String,
+    pub gaze_output: String,
+    pub frame_history: usize,
+    pub states: Vec<StateLayout>,
+    pub segmentation: Option<SegmentationLayout>,
+}
+
+impl ModelLayout {
+    pub fn from_session(session: &Session) -> Self {
+        let metadata = session.metadata().ok();
+        let custom = |key: &str| {
+            metadata
+                .as_ref()
+                .and_then(|metadata| metadata.custom(key).ok().flatten())
+        };
+        let names = |key: &str| {
+            custom(key)
+                .map(|value| {
+                    value
+                        .split(',')
+                        .map(|name| name.trim().to_string())
+                        .filter(|name| !name.is_empty())
+                        .collect::<Vec<_>>()
+                })
+                .unwrap_or_default()
+        };
+
+        let state_inputs = names("state_inputs");
+        let state_outputs = names("state_outputs");
+        if state_inputs.len() != state_outputs.len() {
+            warn!("Model state inputs and outputs don't pair up, ignoring the extra ones");
+        }
+
+        let states = state_inputs
+            .into_iter()
+            .zip(state_outputs)
+            .filter_map(|(input, output)| {
+                let Some(model_input) = session.inputs.iter().find(|i| i.name == input) else {
+                    warn!("Model has no state input {input:?}");
+                    return None;
+                };
+                let shape = model_input
+                    .input_type
+                    .tensor_shape()
+                    .map(|shape| shape.iter().map(|&d| d.max(1) as usize).collect())
+                    .unwrap_or_else(|| vec![1]);
+                Some(StateLayout {
+                    input,
+                    output,
+                    shape,
+                })
+            })
+            .collect::<Vec<StateLayout>>();
+
+        let image_input = custom("image_input")
+            .or_else(|| {
+                session
+                    .inputs
+                    .iter()
+                    .find(|i| !states.iter().any(|s| s.input == i.name))
+                    .map(|i| i.name.clone())
+            })
+            .unwrap_or_default();
+        let gaze_output = custom("gaze_output")
+            .or_else(|| {
+                session
+                    .outputs
+                    .iter()
+                    .find(|o| !states.iter().any(|s| s.output == o.name))
+                    .map(|o| o.name.clone())
+            })
+            .unwrap_or_default();
+
+        // The last dimension is just as likely the image width, so it's never guessed.
+        let frame_history = custom("frame_history")
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(1)
+            .max(1);
+
+        let segmentation = custom("segmentation_classes")
+            .and_then(|classes| SegmentationLayout::from_metadata(&classes));
+
+        ModelLayout {
+            image_input,
+            gaze_output,
+            frame_history,
+            states,
+            segmentation,
+        }
+    }
+
+    pub fn is_temporal(&self) -> bool {
+        self.frame_history > 1 || !self.states.is_empty()
+    }
+}
+
+/// Frames and recurrent state carried between runs of the same eye.
+#[derive(Debug, Default)]
+pub struct EyeHistory {
+    frames: VecDeque<GrayImage>,
+    states: Vec<ArrayD<f32>>,
+    last_update: Option<Instant>,
+}
+
+impl EyeHistory {
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Adds the newest frame and stacks the history into a NHWC tensor, with one channel
+    /// per frame. Until the history fills up the oldest frame is repeated.
+    pub fn input_tensor(&mut self, layout: &ModelLayout, frame: GrayImage) -> Array4<f32> {
+        if self
+            .last_update
+            .is_some_and(|last_update| last_update.elapsed() > HISTORY_TIMEOUT)
+        {
+            self.reset();
+        }
+        self.last_update = Some(Instant::now());
+
+        if layout.frame_history == 1 {
+            self.frames.clear();
+            return build_input_tensor(&frame);
+        }
+
+        self.frames.push_back(frame);
+        while self.frames.len() > layout.frame_history {
+            self.frames.pop_front();
+        }
+
+        let newest = self.frames.back().unwrap();
+        let (w, h) = newest.dimensions();
+        let padding = layout.frame_history - self.frames.len();
+
+        Array4::from_shape_fn(
+            (1, h as usize, w as usize, layout.frame_history),
+            |(_, y, x, i)| {
+                let frame = &self.frames[i.saturating_sub(padding)];
+                frame.get_pixel(x as u32, y as u32)[0] as f32
+            },
+        )
+    }
+}
+
+/// Debug copy of the last model inputs, published at a reduced rate.
+/// Segmentation models also publish their class masks, see `SegmentationClass`.
+#[derive(Clone, Debug, Default)]
+pub struct ModelInputFrame {
+    pub l_input: Option<GrayImage>,
+    pub r_input: Option<GrayImage>,
+    pub l_mask: Option<GrayImage>,
+    pub r_mask: Option<GrayImage>,
+}
+
+pub struct ModelOutput {
+    pub state: EyeGazeState,
+    /// Only for segmentation models.
+    pub mask: Option<GrayImage>,
+    pub features: Option<EyeFeatures>,
+}
+
+/// Runs the model and converts its output into the eye state.
+/// Recurrent state is taken from and stored back into `history`.
+/// Segmentation outputs are turned into the eye state through `mapping`.
+/// `estimates` of the newest frame fill in what the model doesn't report.
+/// Missing or too short outputs are errors, so they count as faults instead of panicking.
+pub fn run_model(
+    model: &mut Session,
+    layout: &ModelLayout,
+    history: &mut EyeHistory,
+    mapping: &SegmentationMapping,
+    input: &Array4<f32>,
+    estimates: ImageEstimates,
+    is_left: bool,
+) -> ort::Result<ModelOutput> {
+    if history.states.len() != layout.states.len() {
+        history.states = layout
+            .states
+            .iter()
+            .map(|state| ArrayD::zeros(IxDyn(&state.shape)))
+            .collect();
+    }
+
+    let mut inputs: Vec<(&str, SessionInputValue)> = vec![(
+        layout.image_input.as_str(),
+        TensorRef::from_array_view(input)?.into(),
+    )];
+    for (state, value) in layout.states.iter().zip(&history.states) {
+        inputs.push((state.input.as_str(), TensorRef::from_array_view(value)?.into()));
+    }
+
+    let outputs = model.run(inputs)?;
+    let output_named = |name: &str| {
+        outputs
+            .get(name)
+            .ok_or_else(|| ort::Error::new(format!("Model has no output {name:?}")))
+    };
+
+    for (state, value) in layout.states.iter().zip(history.states.iter_mut()) {
+        let (shape, data) = output_named(&state.output)?.try_extract_tensor::<f32>()?;
+        let shape = shape.iter().map(|&d| d as usize).collect::<Vec<_>>();
+        if let Ok(new_value) = ArrayD::from_shape_vec(IxDyn(&shape), data.to_vec()) {
+            *value = new_value;
+        }
+    }
+
+    let (output_shape, output) = output_named(&layout.gaze_output)?.try_extract_tensor::<f32>()?;
+
+    if let Some(segmentation) = &layout.segmentation {
+        let Some((mask, probabilities)) = segmentation.decode(output_shape, output) else {
+            return Err(ort::Error::new(format!(
+                "Unexpected segmentation output shape {output_shape:?}"
+            )));
+        };
+        let features = EyeFeatures::from_mask(&mask, &probabilities);
+
+        return Ok(ModelOutput {
+            state: mapping.gaze_state(&features, is_left),
+            mask: Some(mask),
+            features: Some(features),
+        });
+    }
+
+    let &[pitch, yaw, eyelid, ..] = output else {
+        return Err(ort::Error::new(format!(
+            "Expected at least 3 gaze outputs, got {}",
+            output.len()
+        )));
+    };
+
+    let confidence = match output.get(MODEL_CONFIDENCE_OUTPUT) {
+        Some(model_confidence) if model_confidence.is_finite() => model_confidence.clamp(0.0, 1.0),
+        _ => estimates.confidence,
+    };
+    let pupil = match output.get(MODEL_PUPIL_OUTPUT) {
+        Some(model_pupil) if model_pupil.is_finite() => *model_pupil,
+        _ => estimates.pupil,
+    };
+
+    Ok(ModelOutput {
+        state: EyeGazeState {
+            pitch,
+            yaw: yaw * if is_left { 1.0 } else { -1.0 },
+            eyelid,
+            confidence,
+            pupil,
+            valid: true,
+        },
+        mask: None,
+        features: None,
+    })
+}
+
+/// How often the model file is checked for changes on disk.
+const MODEL_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// ONNX Runtime execution providers that can be selected, availability depends
+/// on how ONNX Runtime was built. Unavailable ones fall back to CPU.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExecutionProviderKind {
+    #[default]
+    Cpu,
+    Xnnpack,
+    Nnapi,
+    Cuda,
+    DirectMl,
+    CoreMl,
+}
+
+impl ExecutionProviderKind {
+    /// Accelerator and its availability, `None` for CPU.
+    fn accelerator(self) -> Option<(ort::Result<bool>, ExecutionProviderDispatch)> {
+        match self {
+            ExecutionProviderKind::Cpu => None,
+            ExecutionProviderKind::Xnnpack => {
+                let ep = XNNPACKExecutionProvider::default();
+                Some((ep.is_available(), ep.build()))
+            }
+            ExecutionProviderKind::Nnapi => {
+                let ep = NNAPIExecutionProvider::default();
+                Some((ep.is_available(), ep.build()))
+            }
+            ExecutionProviderKind::Cuda => {
+                let ep = CUDAExecutionProvider::default();
+                Some((ep.is_available(), ep.build()))
+            }
+            ExecutionProviderKind::DirectMl => {
+                let ep = DirectMLExecutionProvider::default();
+                Some((ep.is_available(), ep.build()))
+            }
+            ExecutionProviderKind::CoreMl => {
+                let ep = CoreMLExecutionProvider::default();
+                Some((ep.is_available(), ep.build()))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct InferenceSettings {
+    /// On Android the bundled model is used if there's no file at this path.
+    pub model_path: PathBuf,
+    pub threads_per_eye: usize,
+
+    pub execution_provider: ExecutionProviderKind,
+    /// Only used with parallel execution.
+    pub inter_threads: usize,
+    pub parallel_execution: bool,
+    pub memory_arena: bool,
+    /// Spinning lowers latency at the cost of burning CPU while waiting for work.
+    pub intra_op_spinning: bool,
+    pub inter_op_spinning: bool,
+}
+
+impl Default for InferenceSettings {
+    fn default() -> Self {
+        Self {
+            #[cfg(target_os = "android")]
+            model_path: crate::storage::data_dir().join("model.onnx"),
+            #[cfg(not(target_os = "android"))]
+            model_path: PathBuf::from("./model.onnx"),
+            threads_per_eye: 1,
+
+            execution_provider: ExecutionProviderKind::default(),
+            inter_threads: 1,
+            parallel_execution: false,
+            memory_arena: true,
+            intra_op_spinning: true,
+            inter_op_spinning: true,
+        }
+    }
+}
+
+/// Lets the UI and the control server change inference settings while it's running.
+#[derive(Debug, Default)]
+pub struct InferenceControl {
+    settings: Mutex<InferenceSettings>,
+    reload_requested: AtomicBool,
+    status: Mutex<String>,
+    // Left and right eye, applied on the next frame without reloading the model.
+    preprocessing: Mutex<(PreprocessingSettings, PreprocessingSettings)>,
+    // Left and right eye, only used by segmentation models.
+    segmentation: Mutex<(SegmentationMapping, SegmentationMapping)>,
+    // Last measurements of a segmentation model, to calibrate the mapping against.
+    features: Mutex<(Option<EyeFeatures>, Option<EyeFeatures>)>,
+    output_limits: Mutex<OutputLimits>,
+    faults: FaultCounters,
+}
+
+impl InferenceControl {
+    pub fn preprocessing(&self, eye: Eye) -> PreprocessingSettings {
+        let preprocessing = self.preprocessing.lock().unwrap();
+        match eye {
+            Eye::L => preprocessing.0,
+            Eye::R => preprocessing.1,
+        }
+    }
+
+    pub fn set_preprocessing(&self, eye: Eye, settings: PreprocessingSettings) {
+        let mut preprocessing = self.preprocessing.lock().unwrap();
+        match eye {
+            Eye::L => preprocessing.0 = settings,
+            Eye::R => preprocessing.1 = settings,
+        }
+    }
+
+    pub fn segmentation_mapping(&self, eye: Eye) -> SegmentationMapping {
+        let segmentation = self.segmentation.lock().unwrap();
+        match eye {
+            Eye::L => segmentation.0,
+            Eye::R => segmentation.1,
+        }
+    }
+
+    pub fn set_segmentation_mapping(&self, eye: Eye, mapping: SegmentationMapping) {
+        let mut segmentation = self.segmentation.lock().unwrap();
+        match eye {
+            Eye::L => segmentation.0 = mapping,
+            Eye::R => segmentation.1 = mapping,
+        }
+    }
+
+    /// Latest segmentation measurements, `None` for regressor models.
+    pub fn features(&self, eye: Eye) -> Option<EyeFeatures> {
+        let features = self.features.lock().unwrap();
+        match eye {
+            Eye::L => features.0,
+            Eye::R => features.1,
+        }
+    }
+
+    fn set_features(&self, eye: Eye, eye_features: Option<EyeFeatures>) {
+        let mut features = self.features.lock().unwrap();
+        match eye {
+            Eye::L => features.0 = eye_features,
+            Eye::R => features.1 = eye_features,
+        }
+    }
+
+    pub fn output_limits(&self) -> OutputLimits {
+        *self.output_limits.lock().unwrap()
+    }
+
+    pub fn set_output_limits(&self, limits: OutputLimits) {
+        *self.output_limits.lock().unwrap() = limits;
+    }
+
+    pub fn faults(&self) -> &FaultCounters {
+        &self.faults
+    }
+
+    pub fn settings(&self) -> InferenceSettings {
+        self.settings.lock().unwrap().clone()
+    }
+
+    /// Changes the settings, the session is rebuilt before the next frame.
+    pub fn update_settings(&self, f: impl FnOnce(&mut InferenceSettings)) {
+        f(&mut self.settings.lock().unwrap());
+        self.request_reload();
+    }
+
+    pub fn request_reload(&self) {
+        self.reload_requested.store(true, Ordering::Relaxed);
+    }
+
+    /// Human readable result of the last model load.
+    pub fn status(&self) -> String {
+        self.status.lock().unwrap().clone()
+    }
+
+    fn take_reload_request(&self) -> bool {
+        self.reload_requested.swap(false, Ordering::Relaxed)
+    }
+
+    fn set_status(&self, status: String) {
+        *self.status.lock().unwrap() = status;
+    }
+}
+
+fn model_modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+pub fn new_session_builder(
+    threads: usize,
+    optimization_level: GraphOptimizationLevel,
+) -> ort::Result<SessionBuilder> {
+    Session::builder()?
+        .with_optimization_level(optimization_level)?
+        .with_intra_threads(threads)
+}
+
+fn configured_session_builder(settings: &InferenceSettings) -> ort::Result<SessionBuilder> {
+    new_session_builder(settings.threads_per_eye, GraphOptimizationLevel::Level3)?
+        .with_inter_threads(settings.inter_threads)?
+        .with_parallel_execution(settings.parallel_execution)?
+        .with_intra_op_spinning(settings.intra_op_spinning)?
+        .with_inter_op_spinning(settings.inter_op_spinning)
+}
+
+/// Registers the selected execution provider, falling back to CPU if it's not available.
+/// Returns the reason of the fallback, if any.
+fn with_execution_provider(
+    settings: &InferenceSettings,
+) -> ort::Result<(SessionBuilder, Option<String>)> {
+    let cpu = || {
+        CPUExecutionProvider::default()
+            .with_arena_allocator(settings.memory_arena)
+            .build()
+    };
+
+    let Some((available, accelerator)) = settings.execution_provider.accelerator() else {
+        return Ok((
+            configured_session_builder(settings)?.with_execution_providers([cpu()])?,
+            None,
+        ));
+    };
+
+    let reason = match available {
+        Ok(true) => match configured_session_builder(settings)?
+            .with_execution_providers([accelerator.error_on_failure(), cpu()])
+        {
+            Ok(builder) => return Ok((builder, None)),
+            Err(err) => format!("failed to register: {err}"),
+        },
+        Ok(false) => "not compiled into ONNX Runtime".to_string(),
+        Err(err) => format!("failed to check availability: {err}"),
+    };
+
+    let reason = format!(
+        "{:?} execution provider unavailable, using CPU: {reason}",
+        settings.execution_provider
+    );
+    warn!("{reason}");
+
+    Ok((
+        configured_session_builder(settings)?.with_execution_providers([cpu()])?,
+        Some(reason),
+    ))
+}
+
+/// Returns the reason the execution provider fell back to CPU, if it did.
+pub fn load_session(settings: &InferenceSettings) -> ort::Result<(Session, Option<String>)> {
+    let (session_builder, fallback_reason) = with_execution_provider(settings)?;
+
+    #[cfg(target_os = "android")]
+    if !settings.model_path.exists() {
+        const MODEL_BYTES: &[u8] = include_bytes!("../model.onnx");
+        return Ok((
+            session_builder.commit_from_memory_directly(MODEL_BYTES)?,
+            fallback_reason,
+        ));
+    }
+
+    Ok((
+        session_builder.commit_from_file(&settings.model_path)?,
+        fallback_reason,
+    ))
+}
+
 pub fn eye_inference(
     mut rx: Receiver<EyesFrame>,
     tx: Sender<EyesGazeState>,
-    #[cfg(not(target_os = "android"))] model_path: &str,
-    threads: usize,
+    input_tx: Sender<ModelInputFrame>,
+    control: Arc<InferenceControl>,
 ) -> JoinHandle<()> {
-    #[cfg(not(target_os = "android"))]
-    let model_path = model_path.to_owned();
-
     tokio::task::spawn_blocking(move || {
-        let session_builder = Session::builder()
-            .unwrap()
-            .with_optimization_level(GraphOptimizationLevel::Level3)
-            .unwrap()
-            .with_intra_threads(threads)
-            .unwrap();
-
-        let mut model = {
-            #[cfg(target_os = "android")]
-            {
-                const MODEL_BYTES: &[u8] = include_bytes!("../model.onnx");
-                session_builder.commit_from_memory_directly(MODEL_BYTES)
-            }
+        let mut model: Option<(Session, ModelLayout)> = None;
+        let mut l_history = EyeHistory::default();
+        let mut r_history = EyeHistory::default();
+        let mut l_validator = OutputValidator::default();
+        let mut r_validator = OutputValidator::default();
+        let mut consecutive_run_errors = 0;
+        let mut model_modified = None;
+        let mut last_watch = Instant::now();
+        let mut last_input_publish = Instant::now();
 
-            #[cfg(not(target_os = "android"))]
-            {
-                session_builder.commit_from_file(model_path)
-            }
-        }
-        .unwrap();
+        // Load the model before the first frame.
+        control.request_reload();
 
         loop {
             let eyes_frame = loop {
@@ -65,83 +761,168 @@ pub fn eye_inference(
                 }
             };
 
-            let mut run_eye_inference = |frame_view: image::SubImage<
-                &image::ImageBuffer<image::Rgb<u8>, Vec<u8>>,
-            >,
-                                         is_left: bool| {
-                // TODO: make it grayscale for a bit less operations? But crashes for some reason so far.
-                // let mut frame_view = DynamicImage::ImageRgb8(frame_view.to_image()).grayscale();
-                let mut frame_view = DynamicImage::ImageRgb8(frame_view.to_image());
+            // Rebuild the session if asked to or if the model file has changed.
+            let settings = control.settings();
+            let mut reload = control.take_reload_request();
+            if last_watch.elapsed() > MODEL_WATCH_INTERVAL {
+                last_watch = Instant::now();
+                reload |= model_modified_time(&settings.model_path) != model_modified;
+            }
+
+            if reload {
+                // Remember the time even if loading fails, so a broken file is retried only
+                // after it changes again.
+                model_modified = model_modified_time(&settings.model_path);
+
+                match load_session(&settings) {
+                    Ok((session, fallback_reason)) => {
+                        info!("Loaded model {:?}", settings.model_path);
+                        let mut status = format!(
+                            "Loaded {}, {} thread(s) per eye",
+                            settings.model_path.display(),
+                            settings.threads_per_eye
+                        );
+                        let layout = ModelLayout::from_session(&session);
+                        if layout.is_temporal() {
+                            info!("Temporal model layout: {layout:?}");
+                            status = format!(
+                                "{status}\n{} frame(s) of history, {} state tensor(s)",
+                                layout.frame_history,
+                                layout.states.len()
+                            );
+                        }
+                        if let Some(fallback_reason) = fallback_reason {
+                            status = format!("{status}\n{fallback_reason}");
+                        }
+                        control.set_status(status);
 
-                if !is_left {
-                    frame_view = frame_view.fliph();
+                        // State of the previous model means nothing to the new one.
+                        l_history.reset();
+                        r_history.reset();
+                        model = Some((session, layout));
+                    }
+                    Err(err) => {
+                        error!("Failed to load model {:?}: {err}", settings.model_path);
+                        control.set_status(format!("Failed to load: {err}"));
+                    }
                 }
+            }
 
-                let cropped_frame =
-                    frame_view.view(FRAME_CROP_X, FRAME_CROP_Y, FRAME_CROP_W, FRAME_CROP_H);
+            let Some((model, layout)) = model.as_mut() else {
+                continue;
+            };
 
-                let final_frame = image::imageops::resize(
-                    &cropped_frame.to_image(),
-                    FRAME_RESIZE_W,
-                    FRAME_RESIZE_H,
-                    image::imageops::FilterType::Lanczos3,
-                );
+            // Only copy the inputs when someone is watching.
+            let publish_input = input_tx.receiver_count() > 0
+                && last_input_publish.elapsed() > MODEL_INPUT_DEBUG_INTERVAL;
+            let mut input_frame = ModelInputFrame::default();
 
-                // Panics that the shape is wrong when using this.
-                // let array = ndarray::Array::from_vec(final_frame.into_vec());
-                let array = ndarray::Array::from_iter(final_frame.pixels().map(|p| p[0] as f32));
+            let limits = control.output_limits();
+            let timestamp: Timestamp = eyes_frame.frame.timestamp;
 
-                let array = array
-                    .to_shape((1, FRAME_RESIZE_W as usize, FRAME_RESIZE_H as usize, 1))
-                    .unwrap();
+            let mut run_eye_inference = |frame_view: EyeView, is_left: bool| {
+                let eye = if is_left { Eye::L } else { Eye::R };
+                let preprocessing = control.preprocessing(eye);
+                let (history, validator) = if is_left {
+                    (&mut l_history, &mut l_validator)
+                } else {
+                    (&mut r_history, &mut r_validator)
+                };
+                let (image, estimates) =
+                    model_input_image(&resize_eye(&crop_eye(frame_view, is_left)), &preprocessing);
+                let input = history.input_tensor(layout, image);
+                if publish_input {
+                    let image = Some(tensor_image(&input));
+                    if is_left {
+                        input_frame.l_input = image;
+                    } else {
+                        input_frame.r_input = image;
+                    }
+                }
 
-                let tensor = TensorRef::from_array_view(&array).unwrap();
+                let output = match run_model(
+                    model,
+                    layout,
+                    history,
+                    &control.segmentation_mapping(eye),
+                    &input,
+                    estimates,
+                    is_left,
+                ) {
+                    Ok(output) => output,
+                    Err(err) => {
+                        // Log only the start of a streak, not every frame.
+                        if consecutive_run_errors == 0 {
+                            error!("Inference failed: {err}");
+                        }
+                        consecutive_run_errors += 1;
+                        control.faults.run_errors.fetch_add(1, Ordering::Relaxed);
 
-                let outputs = model.run(ort::inputs![tensor]).unwrap();
-                let output = outputs.iter().next().unwrap().1;
-                let output = output.try_extract_tensor::<f32>().unwrap();
-                let output = output.1;
+                        // The recurrent state may be what broke it.
+                        history.reset();
+
+                        if consecutive_run_errors >= MAX_CONSECUTIVE_RUN_ERRORS {
+                            warn!("{consecutive_run_errors} failed runs in a row, rebuilding session");
+                            consecutive_run_errors = 0;
+                            control.faults.recoveries.fetch_add(1, Ordering::Relaxed);
+                            control.request_reload();
+                        }
 
-                EyeGazeState {
-                    pitch: output[0],
-                    yaw: output[1] * if is_left { 1.0 } else { -1.0 },
-                    eyelid: output[2],
+                        return validator.hold();
+                    }
+                };
+                consecutive_run_errors = 0;
+
+                control.set_features(eye, output.features);
+                if publish_input {
+                    if is_left {
+                        input_frame.l_mask = output.mask;
+                    } else {
+                        input_frame.r_mask = output.mask;
+                    }
+                }
+
+                let (state, fault) = validator.validate(output.state, timestamp, &limits);
+                if let Some(fault) = fault {
+                    control.faults.count(fault);
+                    // Garbage may have ended up in the recurrent state too.
+                    history.reset();
                 }
+                state
             };
 
-            match eyes_frame.frame_type {
+            let eyes_gaze = match eyes_frame.frame_type {
                 EyesFrameType::Both => {
                     let l_state = run_eye_inference(eyes_frame.get_left_view().unwrap(), true);
-                    let r_state: EyeGazeState =
-                        run_eye_inference(eyes_frame.get_right_view().unwrap(), false);
+                    let r_state = run_eye_inference(eyes_frame.get_right_view().unwrap(), false);
 
-                    tx.broadcast_blocking(EyesGazeState::Both {
+                    EyesGazeState::Both {
                         l_state,
                         r_state,
-                        timestamp: eyes_frame.frame.timestamp,
-                    })
-                    .unwrap();
+                        timestamp,
+                    }
                 }
-                EyesFrameType::Left => {
-                    let l_state = run_eye_inference(eyes_frame.get_left_view().unwrap(), true);
+                EyesFrameType::Left => EyesGazeState::Mono {
+                    eye: Eye::L,
+                    state: run_eye_inference(eyes_frame.get_left_view().unwrap(), true),
+                    timestamp,
+                },
+                EyesFrameType::Rigth => EyesGazeState::Mono {
+                    eye: Eye::R,
+                    state: run_eye_inference(eyes_frame.get_right_view().unwrap(), false),
+                    timestamp,
+                },
+            };
 
-                    tx.broadcast_blocking(EyesGazeState::Mono {
-                        eye: Eye::L,
-                        state: l_state,
-                        timestamp: eyes_frame.frame.timestamp,
-                    })
-                    .unwrap();
-                }
-                EyesFrameType::Rigth => {
-                    let r_state = run_eye_inference(eyes_frame.get_right_view().unwrap(), false);
+            if tx.broadcast_blocking(eyes_gaze).is_err() {
+                error!("Channel closed");
+                return;
+            }
 
-                    tx.broadcast_blocking(EyesGazeState::Mono {
-                        eye: Eye::R,
-                        state: r_state,
-                        timestamp: eyes_frame.frame.timestamp,
-                    })
-                    .unwrap();
-                }
+            if publish_input {
+                last_input_publish = Instant::now();
+                // The UI may be behind, a dropped debug frame doesn't matter.
+                let _ = input_tx.try_broadcast(input_frame);
             }
         }
     })
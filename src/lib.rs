@@ -5,8 +5,10 @@ mod camera_dispatcher;
 mod camera_manager;
 mod camera_server;
 mod camera_sources;
+mod control_server;
 mod frame_server;
 mod logging;
+mod storage;
 mod structs;
 
 #[cfg(feature = "gui")]
@@ -22,12 +24,49 @@ mod window_desktop;
 #[cfg(all(target_os = "android", feature = "gui", feature = "openxr-api-layer"))]
 mod window_android;
 
+#[cfg(all(feature = "desktop", feature = "inference"))]
+mod benchmark;
+#[cfg(all(feature = "desktop", feature = "inference"))]
+mod dataset;
+#[cfg(all(feature = "desktop", feature = "inference"))]
+mod evaluate;
+#[cfg(feature = "inference")]
+mod blink;
+#[cfg(feature = "inference")]
+mod calibration;
+#[cfg(feature = "inference")]
+mod combiner;
 #[cfg(feature = "inference")]
 mod data_processing;
 #[cfg(feature = "inference")]
+mod eye_movements;
+#[cfg(feature = "inference")]
+mod filters;
+#[cfg(feature = "inference")]
 mod inference;
 #[cfg(feature = "inference")]
+mod osc_receiver;
+#[cfg(feature = "inference")]
 mod osc_sender;
+#[cfg(feature = "inference")]
+mod post_processing;
+#[cfg(feature = "inference")]
+mod prediction;
+#[cfg(feature = "inference")]
+mod preprocessing;
+#[cfg(feature = "inference")]
+mod recenter;
+#[cfg(feature = "inference")]
+mod segmentation;
+#[cfg(feature = "inference")]
+mod tracking;
+#[cfg(feature = "inference")]
+mod validation;
+#[cfg(feature = "inference")]
+mod vergence;
+
+#[cfg(any(feature = "inference", feature = "openxr-api-layer"))]
+mod output_profiles;
 
 #[cfg(feature = "desktop")]
 pub mod desktop;
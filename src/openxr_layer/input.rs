@@ -52,6 +52,7 @@ impl Default for InputState {
 pub enum UiEvent {
     PointerMove { x: f32, y: f32 },
     PointerButton { down: bool },
+    SpecialGesture,
 }
 
 pub struct Inputs {
@@ -250,7 +251,7 @@ impl Inputs {
                             self.input_state.combo_count[hand_i] += 1;
                             if self.input_state.combo_count[hand_i] >= 2 {
                                 // FIRE SPECIAL GESTURE
-                                // special_gesture_triggered();
+                                self.events.push(UiEvent::SpecialGesture);
                                 self.input_state.combo_count[hand_i] = 0;
                                 self.input_state.last_combo_time[hand_i] = None;
                             }
@@ -4,6 +4,7 @@ use std::{
     ffi::{CString, c_char, c_void},
     ptr,
     sync::{Condvar, Mutex},
+    time::{Duration, SystemTime},
 };
 
 use log::{debug, info, trace};
@@ -32,6 +33,8 @@ use crate::{
         modules::{BoundaryVisibilityStatus, OpenXRModules},
     },
     openxr_output::OPENXR_OUTPUT_BRIDGE,
+    output_profiles::Output,
+    structs::Timestamp,
 };
 
 pub static mut LAYER: Lazy<OpenXRLayer> = Lazy::new(OpenXRLayer::new);
@@ -92,6 +95,50 @@ fn quat_from_pitch_yaw(pitch: f32, yaw: f32) -> quat::Quaternion<f32> {
     )
 }
 
+/// Nanoseconds of the clock `XrTime` is based on. Android runtimes use `CLOCK_MONOTONIC`,
+/// elsewhere the epoch is up to the runtime and times can't be converted.
+#[cfg(target_os = "android")]
+fn xr_clock_now_nanos() -> Option<i64> {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    Some(now.tv_sec as i64 * 1_000_000_000 + now.tv_nsec as i64)
+}
+
+#[cfg(not(target_os = "android"))]
+fn xr_clock_now_nanos() -> Option<i64> {
+    None
+}
+
+/// Converts a runtime time to the timestamps used by the eye tracking pipeline.
+/// Without a known clock the time is taken as now, so nothing is predicted.
+fn xr_time_to_timestamp(time: xr_sys::Time) -> Timestamp {
+    let now = SystemTime::now();
+    let Some(xr_now) = xr_clock_now_nanos() else {
+        return now;
+    };
+    let offset = time.as_nanos() - xr_now;
+    if offset >= 0 {
+        now + Duration::from_nanos(offset as u64)
+    } else {
+        now - Duration::from_nanos(offset.unsigned_abs())
+    }
+}
+
+/// Without a known clock this is `requested`, the time the runtime asked for.
+fn timestamp_to_xr_time(timestamp: Timestamp, requested: xr_sys::Time) -> xr_sys::Time {
+    let Some(xr_now) = xr_clock_now_nanos() else {
+        return requested;
+    };
+    let offset = match timestamp.duration_since(SystemTime::now()) {
+        Ok(ahead) => ahead.as_nanos() as i64,
+        Err(err) => -(err.duration().as_nanos() as i64),
+    };
+    xr_sys::Time::from_nanos(xr_now + offset)
+}
+
 pub struct OpenXRLayer {
     pub instance: Option<xr::Instance>,
     pub session: Option<xr::Session<xr::OpenGlEs>>,
@@ -386,6 +433,8 @@ impl OpenXRLayer {
 
         #[cfg(feature = "gui")]
         {
+            use crate::ui::{UI_OVERLAY_DISTANCE, UI_OVERLAY_HEIGHT, UI_OVERLAY_WIDTH};
+
             let q = quat::from_euler_angles(
                 quat::RotationType::Extrinsic,
                 quat::RotationSequence::XYZ,
@@ -397,7 +446,7 @@ impl OpenXRLayer {
                     position: Vector3f {
                         x: 0.0,
                         y: 0.0,
-                        z: -1.5,
+                        z: -UI_OVERLAY_DISTANCE,
                     },
                     orientation: Quaternionf {
                         w: q.0,
@@ -407,8 +456,8 @@ impl OpenXRLayer {
                     },
                 },
                 size: Extent2Df {
-                    width: 1.6,
-                    height: 0.9,
+                    width: UI_OVERLAY_WIDTH,
+                    height: UI_OVERLAY_HEIGHT,
                 },
             };
 
@@ -1107,9 +1156,12 @@ impl OpenXRLayer {
                 .map(|mutex| mutex.lock().expect("failed to lock OpenXR output bridge"))
                 // False if there's no bridge yet.
                 .is_some_and(|mut bridge| {
-                    // False if there has been no data yet.
+                    // False if there has been no data yet. Idle updates keep coming
+                    // without cameras, so an eye also has to be tracked.
                     bridge.get_eyes_state().is_some_and(|gaze| {
-                        gaze.timestamp.elapsed().unwrap() < std::time::Duration::from_millis(50)
+                        (gaze.l_valid || gaze.r_valid)
+                            && gaze.timestamp.elapsed().unwrap_or_default()
+                                < std::time::Duration::from_millis(50)
                     })
                 })
                 .into();
@@ -1171,21 +1223,33 @@ impl OpenXRLayer {
 
             let location: &mut openxr_sys::SpaceLocation = &mut *location;
 
-            let Some(eyes_state) = OPENXR_OUTPUT_BRIDGE
+            let mut bridge = OPENXR_OUTPUT_BRIDGE
                 .get()
                 .expect("requested for gaze, but bridge was not initialized yet")
                 .lock()
-                .expect("failed to lock OpenXR output bridge")
-                .get_eyes_state()
-            else {
+                .expect("failed to lock OpenXR output bridge");
+            let profile = bridge.profile(Output::OpenXrGaze);
+            let Some(eyes_state) = bridge.get_eyes_state() else {
                 location.location_flags &= !xr_sys::SpaceLocationFlags::POSITION_TRACKED;
                 location.location_flags &= !xr_sys::SpaceLocationFlags::ORIENTATION_TRACKED;
                 return xr_sys::Result::SUCCESS;
             };
+            drop(bridge);
+
+            // Fallback values of lost eyes aren't a gaze.
+            if !eyes_state.l_valid && !eyes_state.r_valid {
+                location.location_flags &= !xr_sys::SpaceLocationFlags::POSITION_TRACKED;
+                location.location_flags &= !xr_sys::SpaceLocationFlags::ORIENTATION_TRACKED;
+                return xr_sys::Result::SUCCESS;
+            }
 
             location.location_flags |= xr_sys::SpaceLocationFlags::POSITION_TRACKED;
             location.location_flags |= xr_sys::SpaceLocationFlags::ORIENTATION_TRACKED;
 
+            // Answer for the requested time instead of the last sample.
+            let sample_time = timestamp_to_xr_time(eyes_state.timestamp, time);
+            let eyes_state = profile.apply(&eyes_state.predict(xr_time_to_timestamp(time)));
+
             let q_gaze_in_view = quat_from_pitch_yaw(eyes_state.gaze_pitch, eyes_state.gaze_yaw);
             let q_base_in_view: quat::Quaternion<f32> = (
                 base_from_view_space.orientation.w,
@@ -1199,11 +1263,8 @@ impl OpenXRLayer {
             // Convert gaze from the VIEW space into `base_space`.
             let q_gaze_in_base = quat::mul(q_base_in_view, q_gaze_in_view);
 
-            location.pose.position = Vector3f {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            };
+            let [x, y, z] = eyes_state.gaze_origin;
+            location.pose.position = Vector3f { x, y, z };
             location.pose.orientation = Quaternionf {
                 w: q_gaze_in_base.0,
                 x: q_gaze_in_base.1[0],
@@ -1216,7 +1277,7 @@ impl OpenXRLayer {
             if !location.next.is_null() {
                 let eye_gaze_sample_time =
                     &mut *(location.next as *mut xr_sys::EyeGazeSampleTimeEXT);
-                eye_gaze_sample_time.time = xr_sys::Time::from_nanos(0);
+                eye_gaze_sample_time.time = sample_time;
                 // println!("locate_space {:?}", eye_gaze_sample_time);
             }
 
@@ -1275,20 +1336,29 @@ impl OpenXRLayer {
             );
 
             // Retrieve the gaze data.
-            let eyes_state = OPENXR_OUTPUT_BRIDGE
-                .get()
-                .expect("requested for gaze, but bridge was not initialized yet")
-                .lock()
-                .expect("failed to lock OpenXR output bridge")
-                .get_eyes_state();
+            let (eyes_state, profile) = {
+                let mut bridge = OPENXR_OUTPUT_BRIDGE
+                    .get()
+                    .expect("requested for gaze, but bridge was not initialized yet")
+                    .lock()
+                    .expect("failed to lock OpenXR output bridge");
+                (
+                    bridge.get_eyes_state(),
+                    bridge.profile(Output::OpenXrSocial),
+                )
+            };
 
             let Some(eyes_state) = eyes_state else {
                 expression_weights.is_valid = false.into();
                 return xr_sys::Result::SUCCESS;
             };
+            let eyes_state =
+                profile.apply(&eyes_state.predict(xr_time_to_timestamp(expression_info.time)));
 
-            // Very confident...
-            face_confidences.fill(1.0);
+            // Only the upper face (eyes) is tracked, the lower face weights are always zero.
+            face_confidences[FaceConfidence2FB::LOWER_FACE.into_raw() as usize] = 0.0;
+            face_confidences[FaceConfidence2FB::UPPER_FACE.into_raw() as usize] =
+                f32::min(eyes_state.l_confidence, eyes_state.r_confidence);
 
             let remap = |value: f32, low1: f32, high1: f32, low2: f32, high2: f32| {
                 (low2 + (value - low1) * (high2 - low2) / (high1 - low1)).clamp(0.0, 1.0)
@@ -1296,19 +1366,14 @@ impl OpenXRLayer {
 
             for (i, mut _face_expression) in face_expressions.iter_mut().enumerate() {
                 *_face_expression = match FaceExpression2FB::from_raw(i as i32) {
-                    FaceExpression2FB::UPPER_LID_RAISER_L => {
-                        remap(eyes_state.l_eyelid, 0.75, 1.0, 0.0, 1.0)
-                    }
-                    FaceExpression2FB::UPPER_LID_RAISER_R => {
-                        remap(eyes_state.r_eyelid, 0.75, 1.0, 0.0, 1.0)
-                    }
+                    FaceExpression2FB::UPPER_LID_RAISER_L => eyes_state.l_wide,
+                    FaceExpression2FB::UPPER_LID_RAISER_R => eyes_state.r_wide,
 
-                    FaceExpression2FB::EYES_CLOSED_L => {
-                        remap(eyes_state.l_eyelid, 0.75, 0.0, 0.0, 1.0)
-                    }
-                    FaceExpression2FB::EYES_CLOSED_R => {
-                        remap(eyes_state.r_eyelid, 0.75, 0.0, 0.0, 1.0)
-                    }
+                    FaceExpression2FB::EYES_CLOSED_L => eyes_state.l_closed,
+                    FaceExpression2FB::EYES_CLOSED_R => eyes_state.r_closed,
+
+                    FaceExpression2FB::LID_TIGHTENER_L => eyes_state.l_squint,
+                    FaceExpression2FB::LID_TIGHTENER_R => eyes_state.r_squint,
 
                     FaceExpression2FB::EYES_LOOK_LEFT_L => {
                         remap(eyes_state.l_yaw, 0.0, -45.0, 0.0, 1.0)
@@ -1317,10 +1382,10 @@ impl OpenXRLayer {
                         remap(eyes_state.l_yaw, 0.0, 45.0, 0.0, 1.0)
                     }
                     FaceExpression2FB::EYES_LOOK_UP_L => {
-                        remap(eyes_state.pitch, 0.0, 45.0, 0.0, 1.0)
+                        remap(eyes_state.l_pitch, 0.0, 45.0, 0.0, 1.0)
                     }
                     FaceExpression2FB::EYES_LOOK_DOWN_L => {
-                        remap(eyes_state.pitch, 0.0, -45.0, 0.0, 1.0)
+                        remap(eyes_state.l_pitch, 0.0, -45.0, 0.0, 1.0)
                     }
 
                     FaceExpression2FB::EYES_LOOK_LEFT_R => {
@@ -1330,10 +1395,10 @@ impl OpenXRLayer {
                         remap(eyes_state.r_yaw, 0.0, 45.0, 0.0, 1.0)
                     }
                     FaceExpression2FB::EYES_LOOK_UP_R => {
-                        remap(eyes_state.pitch, 0.0, 45.0, 0.0, 1.0)
+                        remap(eyes_state.r_pitch, 0.0, 45.0, 0.0, 1.0)
                     }
                     FaceExpression2FB::EYES_LOOK_DOWN_R => {
-                        remap(eyes_state.pitch, 0.0, -45.0, 0.0, 1.0)
+                        remap(eyes_state.r_pitch, 0.0, -45.0, 0.0, 1.0)
                     }
 
                     _ => 0.0,
@@ -1347,7 +1412,8 @@ impl OpenXRLayer {
             }
 
             expression_weights.data_source = xr_sys::FaceTrackingDataSource2FB::VISUAL;
-            expression_weights.is_eye_following_blendshapes_valid = true.into();
+            expression_weights.is_eye_following_blendshapes_valid =
+                (eyes_state.l_valid || eyes_state.r_valid).into();
             expression_weights.is_valid = true.into();
             expression_weights.time = expression_info.time;
 
@@ -1424,14 +1490,19 @@ impl OpenXRLayer {
             const EYE_POSITION_LEFT_FB: usize = 0;
             const EYE_POSITION_RIGHT_FB: usize = 1;
 
-            let eyes_state = OPENXR_OUTPUT_BRIDGE
-                .get()
-                .expect("requested for gaze, but bridge was not initialized yet")
-                .lock()
-                .expect("failed to lock OpenXR output bridge")
-                .get_eyes_state();
+            let (eyes_state, profile) = {
+                let mut bridge = OPENXR_OUTPUT_BRIDGE
+                    .get()
+                    .expect("requested for gaze, but bridge was not initialized yet")
+                    .lock()
+                    .expect("failed to lock OpenXR output bridge");
+                (
+                    bridge.get_eyes_state(),
+                    bridge.profile(Output::OpenXrSocial),
+                )
+            };
 
-            let pitch_yaw_to_pose = |pitch: f32, yaw: f32, is_left: bool| {
+            let pitch_yaw_to_pose = |pitch: f32, yaw: f32, eye_x: f32| {
                 let mut q_gaze_in_view = quat_from_pitch_yaw(pitch, yaw);
 
                 let q_base_in_view: quat::Quaternion<f32> = (
@@ -1454,10 +1525,9 @@ impl OpenXRLayer {
                         z: q_gaze_in_base.1[2],
                     },
                     position: Vector3f {
-                        // TODO: can't find anything in the specs about this, but QPro seems to include IPD
-                        // (at least some, idk if it's actual IPD) to position eye gaze origins.
-                        // Steam Link refuses to work without this.
-                        x: if is_left { -0.0325 } else { 0.0325 },
+                        // Can't find anything in the specs about this, but QPro seems to include IPD
+                        // to position eye gaze origins. Steam Link refuses to work without this.
+                        x: eye_x,
                         y: 0.0,
                         z: 0.0,
                     },
@@ -1470,16 +1540,18 @@ impl OpenXRLayer {
 
                 return xr_sys::Result::SUCCESS;
             };
+            let eyes_state =
+                profile.apply(&eyes_state.predict(xr_time_to_timestamp(gaze_info.time)));
 
             eye_gazes.gaze[EYE_POSITION_LEFT_FB] = openxr_sys::EyeGazeFB {
-                is_valid: true.into(),
-                gaze_pose: pitch_yaw_to_pose(eyes_state.pitch, eyes_state.l_yaw, true),
-                gaze_confidence: 1.0,
+                is_valid: eyes_state.l_valid.into(),
+                gaze_pose: pitch_yaw_to_pose(eyes_state.l_pitch, eyes_state.l_yaw, -eyes_state.ipd / 2.0),
+                gaze_confidence: eyes_state.l_confidence,
             };
             eye_gazes.gaze[EYE_POSITION_RIGHT_FB] = openxr_sys::EyeGazeFB {
-                is_valid: true.into(),
-                gaze_pose: pitch_yaw_to_pose(eyes_state.pitch, eyes_state.r_yaw, false),
-                gaze_confidence: 1.0,
+                is_valid: eyes_state.r_valid.into(),
+                gaze_pose: pitch_yaw_to_pose(eyes_state.r_pitch, eyes_state.r_yaw, eyes_state.ipd / 2.0),
+                gaze_confidence: eyes_state.r_confidence,
             };
             eye_gazes.time = gaze_info.time;
         }
@@ -1,10 +1,11 @@
 use std::{
-    sync::{Mutex, OnceLock},
+    sync::{Arc, Mutex, OnceLock},
     time::SystemTime,
 };
 
 use async_broadcast::{InactiveReceiver, Receiver};
 
+use crate::output_profiles::{Output, OutputProfile, OutputProfileControl};
 use crate::structs::CombinedEyeGazeState;
 
 pub static OPENXR_OUTPUT_BRIDGE: OnceLock<Mutex<OpenXROutputBridge>> = OnceLock::new();
@@ -12,16 +13,25 @@ pub static OPENXR_OUTPUT_BRIDGE: OnceLock<Mutex<OpenXROutputBridge>> = OnceLock:
 pub struct OpenXROutputBridge {
     receiver: Receiver<CombinedEyeGazeState>,
     last_state: Option<CombinedEyeGazeState>,
+    profiles: Arc<OutputProfileControl>,
 }
 
 impl OpenXROutputBridge {
-    fn new(receiver: &InactiveReceiver<CombinedEyeGazeState>) -> Self {
+    fn new(
+        receiver: &InactiveReceiver<CombinedEyeGazeState>,
+        profiles: Arc<OutputProfileControl>,
+    ) -> Self {
         Self {
             receiver: receiver.activate_cloned(),
             last_state: None,
+            profiles,
         }
     }
 
+    pub fn profile(&self, output: Output) -> OutputProfile {
+        self.profiles.profile(output)
+    }
+
     pub fn get_eyes_state(&mut self) -> Option<CombinedEyeGazeState> {
         let state = loop {
             match self.receiver.try_recv() {
@@ -40,6 +50,9 @@ impl OpenXROutputBridge {
     }
 }
 
-pub fn start_openxr_output(receiver: &InactiveReceiver<CombinedEyeGazeState>) {
-    OPENXR_OUTPUT_BRIDGE.get_or_init(|| Mutex::new(OpenXROutputBridge::new(receiver)));
+pub fn start_openxr_output(
+    receiver: &InactiveReceiver<CombinedEyeGazeState>,
+    profiles: Arc<OutputProfileControl>,
+) {
+    OPENXR_OUTPUT_BRIDGE.get_or_init(|| Mutex::new(OpenXROutputBridge::new(receiver, profiles)));
 }
@@ -0,0 +1,74 @@
+use std::sync::Arc;
+
+use log::{error, info, warn};
+use rosc::{OscMessage, OscPacket, OscType, decoder};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+
+use crate::recenter::RecenterControl;
+
+/// Avatar parameter, so a button in the VRChat expression menu can trigger it.
+const RECENTER_ADDRESS: &str = "/avatar/parameters/ETVR/Recenter";
+
+pub fn start_osc_receiver(
+    osc_in_address: String,
+    recenter: Arc<RecenterControl>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let sock = match UdpSocket::bind(&osc_in_address).await {
+            Ok(sock) => sock,
+            Err(err) => {
+                error!("Failed to start OSC receiver on {osc_in_address}: {err:?}");
+                return;
+            }
+        };
+
+        info!("Receiving OSC on {osc_in_address}");
+
+        let mut buf = [0u8; decoder::MTU];
+        loop {
+            let size = match sock.recv(&mut buf).await {
+                Ok(size) => size,
+                Err(err) => {
+                    warn!("Failed to receive OSC: {err:?}");
+                    continue;
+                }
+            };
+
+            match decoder::decode_udp(&buf[..size]) {
+                Ok((_, packet)) => handle_packet(&packet, &recenter),
+                Err(err) => warn!("Invalid OSC packet: {err:?}"),
+            }
+        }
+    })
+}
+
+fn handle_packet(packet: &OscPacket, recenter: &RecenterControl) {
+    match packet {
+        OscPacket::Message(message) => handle_message(message, recenter),
+        OscPacket::Bundle(bundle) => {
+            for packet in &bundle.content {
+                handle_packet(packet, recenter);
+            }
+        }
+    }
+}
+
+fn handle_message(message: &OscMessage, recenter: &RecenterControl) {
+    if message.addr != RECENTER_ADDRESS {
+        return;
+    }
+
+    // Buttons send true on press and false on release, only the press triggers.
+    let pressed = match message.args.first() {
+        None => true,
+        Some(OscType::Bool(value)) => *value,
+        Some(OscType::Int(value)) => *value != 0,
+        Some(OscType::Float(value)) => *value > 0.5,
+        Some(_) => false,
+    };
+    if pressed {
+        info!("Recenter requested over OSC");
+        recenter.request();
+    }
+}
@@ -1,114 +1,448 @@
-use std::time::SystemTime;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
 
 use async_broadcast::Receiver;
 use const_format::concatcp;
+use log::{info, warn};
 use rosc::{OscBundle, OscMessage, OscPacket, OscType, encoder};
 use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
 use tokio_stream::StreamExt;
 
-use crate::structs::CombinedEyeGazeState;
+use crate::output_profiles::{Output, OutputProfile, OutputProfileControl};
+use crate::structs::{CombinedEyeGazeState, EyeTrackingState};
+
+/// Destinations that couldn't be resolved or connected are retried this often.
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// VRCFT v2 `EyeLid` of a relaxed eye, fixed by the protocol: 0 is closed, 1 wide open.
+/// Unrelated to the tracker's own neutral eyelid, which comes from calibration.
+const VRCFT_EYELID_RELAXED: f32 = 0.75;
+
+/// Message sets sent to a destination.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OscProtocols {
+    /// `/tracking/eye/LeftRightPitchYaw`.
+    pub vrchat_native: bool,
+    /// `/tracking/eye/EyesClosedAmount`, avatars may also animate their own eyelids.
+    pub eyes_closed: bool,
+    pub vrcft_v2: bool,
+    /// Not part of any standard protocol, for avatars that want to react to tracking quality.
+    pub confidence: bool,
+    /// Not part of any standard protocol either, for avatars with depth of field effects.
+    pub focus_distance: bool,
+}
+
+impl Default for OscProtocols {
+    fn default() -> Self {
+        Self {
+            vrchat_native: true,
+            eyes_closed: true,
+            vrcft_v2: true,
+            confidence: false,
+            focus_distance: false,
+        }
+    }
+}
+
+impl OscProtocols {
+    fn none() -> Self {
+        Self {
+            vrchat_native: false,
+            eyes_closed: false,
+            vrcft_v2: false,
+            confidence: false,
+            focus_distance: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OscDestination {
+    /// `host:port`, host names are resolved when connecting.
+    pub address: String,
+    pub protocols: OscProtocols,
+}
+
+impl OscDestination {
+    pub fn new(address: String) -> Self {
+        Self {
+            address,
+            protocols: OscProtocols::default(),
+        }
+    }
+}
+
+/// `host:port` with the default protocols, or e.g. `host:port=native,eyes-closed` with
+/// only the listed ones out of `native`, `eyes-closed`, `vrcft`, `confidence` and `focus`.
+impl FromStr for OscDestination {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, protocol_list) = match s.split_once('=') {
+            Some((address, protocol_list)) => (address, Some(protocol_list)),
+            None => (s, None),
+        };
+        if address.is_empty() {
+            return Err(format!("missing address in {s:?}"));
+        }
+
+        let mut destination = Self::new(address.to_string());
+        if let Some(protocol_list) = protocol_list {
+            let protocols = &mut destination.protocols;
+            *protocols = OscProtocols::none();
+            for protocol in protocol_list.split(',').map(str::trim) {
+                match protocol {
+                    "native" => protocols.vrchat_native = true,
+                    "eyes-closed" => protocols.eyes_closed = true,
+                    "vrcft" => protocols.vrcft_v2 = true,
+                    "confidence" => protocols.confidence = true,
+                    "focus" => protocols.focus_distance = true,
+                    _ => return Err(format!("unknown OSC protocol {protocol:?}")),
+                }
+            }
+        }
+        Ok(destination)
+    }
+}
+
+/// OSC destinations shared with the UI and the control socket.
+#[derive(Debug)]
+pub struct OscControl {
+    destinations: Mutex<Vec<OscDestination>>,
+    generation: AtomicU64,
+}
+
+impl Default for OscControl {
+    fn default() -> Self {
+        Self {
+            destinations: Mutex::new(vec![OscDestination::new("localhost:9000".to_string())]),
+            generation: AtomicU64::new(0),
+        }
+    }
+}
+
+impl OscControl {
+    pub fn destinations(&self) -> Vec<OscDestination> {
+        self.destinations.lock().unwrap().clone()
+    }
+
+    pub fn set_destinations(&self, destinations: Vec<OscDestination>) {
+        *self.destinations.lock().unwrap() = destinations;
+        self.generation.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Changes every time the destinations do.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Relaxed)
+    }
+}
+
+/// Socket of a single destination. Each destination gets its own connected socket, so
+/// an unreachable one only fails its own sends.
+struct Connection {
+    destination: OscDestination,
+    socket: Option<UdpSocket>,
+    /// Resolving and connecting runs in its own task, so a slow lookup of one address
+    /// doesn't hold up the other destinations.
+    connecting: Option<JoinHandle<std::io::Result<UdpSocket>>>,
+    last_attempt: Option<Instant>,
+    /// Errors are logged once, until sending works again.
+    failing: bool,
+}
+
+impl Connection {
+    fn new(destination: OscDestination) -> Self {
+        Self {
+            destination,
+            socket: None,
+            connecting: None,
+            last_attempt: None,
+            failing: false,
+        }
+    }
+
+    async fn connect(address: String) -> std::io::Result<UdpSocket> {
+        let sock = UdpSocket::bind("0.0.0.0:0").await?;
+        sock.connect(address).await?;
+        Ok(sock)
+    }
+
+    /// Picks up a finished connection attempt or starts a new one, without waiting for it.
+    async fn poll_connect(&mut self) {
+        let address = &self.destination.address;
+
+        if let Some(connecting) = self
+            .connecting
+            .take_if(|connecting| connecting.is_finished())
+        {
+            match connecting.await.unwrap_or_else(|err| Err(err.into())) {
+                Ok(sock) => {
+                    info!("Sending OSC to {address}");
+                    self.socket = Some(sock);
+                }
+                Err(err) => warn!("Failed to connect OSC destination {address}: {err:?}"),
+            }
+            return;
+        }
+
+        if self.connecting.is_some()
+            || self
+                .last_attempt
+                .is_some_and(|last_attempt| last_attempt.elapsed() < RETRY_INTERVAL)
+        {
+            return;
+        }
+        self.last_attempt = Some(Instant::now());
+        self.connecting = Some(tokio::spawn(Self::connect(address.clone())));
+    }
+
+    /// Packets are dropped until the destination is connected.
+    async fn send(&mut self, packet: &[u8]) {
+        if self.socket.is_none() {
+            self.poll_connect().await;
+        }
+
+        let Some(sock) = &self.socket else {
+            return;
+        };
+        // Nothing listening shows up as ICMP errors on later sends, the receiver may
+        // just not be running yet.
+        match sock.send(packet).await {
+            Ok(_) => self.failing = false,
+            Err(err) => {
+                if !self.failing {
+                    warn!(
+                        "Failed to send OSC to {}: {err:?}",
+                        self.destination.address
+                    );
+                }
+                self.failing = true;
+            }
+        }
+    }
+}
+
+/// Encoded packets of one gaze state, each built only if some destination wants it.
+struct Packets {
+    vrchat_native: Option<Vec<u8>>,
+    eyes_closed: Option<Vec<u8>>,
+    vrcft_v2: Option<Vec<u8>>,
+    confidence: Option<Vec<u8>>,
+    focus_distance: Option<Vec<u8>>,
+}
+
+impl Packets {
+    fn encode(
+        combined_eyes: &CombinedEyeGazeState,
+        connections: &[Connection],
+        profiles: &OutputProfileControl,
+    ) -> Self {
+        let wanted = |protocol: fn(&OscProtocols) -> bool| {
+            connections
+                .iter()
+                .any(|connection| protocol(&connection.destination.protocols))
+        };
+
+        let native_eyes = profiles.profile(Output::OscNative).apply(combined_eyes);
+
+        Self {
+            vrchat_native: wanted(|p| p.vrchat_native).then(|| vrchat_native_packet(&native_eyes)),
+            eyes_closed: wanted(|p| p.eyes_closed).then(|| eyes_closed_packet(&native_eyes)),
+            vrcft_v2: wanted(|p| p.vrcft_v2)
+                .then(|| vrcft_v2_packet(combined_eyes, &profiles.profile(Output::Vrcft))),
+            confidence: wanted(|p| p.confidence).then(|| confidence_packet(combined_eyes)),
+            focus_distance: wanted(|p| p.focus_distance)
+                .then(|| focus_distance_packet(combined_eyes)),
+        }
+    }
+
+    fn for_protocols(&self, protocols: &OscProtocols) -> impl Iterator<Item = &Vec<u8>> {
+        [
+            (protocols.vrchat_native, &self.vrchat_native),
+            (protocols.eyes_closed, &self.eyes_closed),
+            (protocols.vrcft_v2, &self.vrcft_v2),
+            (protocols.confidence, &self.confidence),
+            (protocols.focus_distance, &self.focus_distance),
+        ]
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .filter_map(|(_, packet)| packet.as_ref())
+    }
+}
 
 pub fn start_osc_sender(
     mut rx: Receiver<CombinedEyeGazeState>,
-    osc_out_address: String,
-) -> tokio::task::JoinHandle<()> {
+    control: Arc<OscControl>,
+    profiles: Arc<OutputProfileControl>,
+) -> JoinHandle<()> {
     tokio::spawn(async move {
-        let sock = UdpSocket::bind("0.0.0.0:0").await.unwrap();
-        sock.connect(osc_out_address).await.unwrap();
-
-        const VRCHAT_NATIVE: bool = true;
-        const VRCFT_V2: bool = true;
+        let mut connections = Vec::<Connection>::new();
+        let mut generation = None;
 
         while let Some(combined_eyes) = rx.next().await {
-            if VRCHAT_NATIVE {
-                const SEND_EYES_CLOSED: bool = true;
-
-                sock.send(
-                    &encoder::encode(&OscPacket::Message(OscMessage {
-                        addr: "/tracking/eye/LeftRightPitchYaw".to_string(),
-                        args: vec![
-                            OscType::Float(combined_eyes.pitch),
-                            OscType::Float(combined_eyes.l_yaw),
-                            OscType::Float(combined_eyes.pitch),
-                            OscType::Float(combined_eyes.r_yaw),
-                        ],
-                    }))
-                    .unwrap(),
-                )
-                .await
-                .unwrap();
-
-                if SEND_EYES_CLOSED {
-                    let vrc_eyelids = f32::clamp(
-                        1.0 - (combined_eyes.l_eyelid + combined_eyes.r_eyelid) / 0.75 / 2.0,
-                        0.0,
-                        1.0,
-                    );
-                    sock.send(
-                        &encoder::encode(&OscPacket::Message(OscMessage {
-                            addr: "/tracking/eye/EyesClosedAmount".to_string(),
-                            args: vec![OscType::Float(vrc_eyelids)],
-                        }))
-                        .unwrap(),
-                    )
-                    .await
-                    .unwrap();
+            if generation != Some(control.generation()) {
+                generation = Some(control.generation());
+
+                // Keep the sockets of addresses that are still there.
+                let mut previous = std::mem::take(&mut connections);
+                for destination in control.destinations() {
+                    let connection = match previous
+                        .iter()
+                        .position(|c| c.destination.address == destination.address)
+                    {
+                        Some(index) => Connection {
+                            destination,
+                            ..previous.swap_remove(index)
+                        },
+                        None => Connection::new(destination),
+                    };
+                    connections.push(connection);
                 }
             }
 
-            if VRCFT_V2 {
-                const VRCFT_OSC_PREFIX: &str = "/avatar/parameters/FT/v2/";
-
-                let l_yaw_norm = combined_eyes.l_yaw.to_radians().sin();
-                let l_pitch_norm = combined_eyes.pitch.to_radians().sin();
-                let l_eyelid = combined_eyes.l_eyelid;
-
-                let r_yaw_norm = combined_eyes.r_yaw.to_radians().sin();
-                let r_pitch_norm = combined_eyes.pitch.to_radians().sin();
-                let r_eyelid = combined_eyes.r_eyelid;
-                let pitch_norm = ((combined_eyes.pitch + combined_eyes.pitch) / 2.0)
-                    .to_radians()
-                    .sin();
-
-                sock.send(
-                    &encoder::encode(&OscPacket::Bundle(OscBundle {
-                        timetag: SystemTime::now().try_into().unwrap(),
-                        content: vec![
-                            OscPacket::Message(OscMessage {
-                                addr: concatcp!(VRCFT_OSC_PREFIX, "EyeY").to_string(),
-                                args: vec![OscType::Float(-pitch_norm)],
-                            }),
-                            OscPacket::Message(OscMessage {
-                                addr: concatcp!(VRCFT_OSC_PREFIX, "EyeLeftX").to_string(),
-                                args: vec![OscType::Float(l_yaw_norm)],
-                            }),
-                            OscPacket::Message(OscMessage {
-                                addr: concatcp!(VRCFT_OSC_PREFIX, "EyeLeftY").to_string(),
-                                args: vec![OscType::Float(-l_pitch_norm)],
-                            }),
-                            OscPacket::Message(OscMessage {
-                                addr: concatcp!(VRCFT_OSC_PREFIX, "EyeLidLeft").to_string(),
-                                args: vec![OscType::Float(l_eyelid)],
-                            }),
-                            OscPacket::Message(OscMessage {
-                                addr: concatcp!(VRCFT_OSC_PREFIX, "EyeRightX").to_string(),
-                                args: vec![OscType::Float(r_yaw_norm)],
-                            }),
-                            OscPacket::Message(OscMessage {
-                                addr: concatcp!(VRCFT_OSC_PREFIX, "EyeRightY").to_string(),
-                                args: vec![OscType::Float(-r_pitch_norm)],
-                            }),
-                            OscPacket::Message(OscMessage {
-                                addr: concatcp!(VRCFT_OSC_PREFIX, "EyeLidRight").to_string(),
-                                args: vec![OscType::Float(r_eyelid)],
-                            }),
-                        ],
-                    }))
-                    .unwrap(),
-                )
-                .await
-                .unwrap();
+            // Without any tracked eye, stop sending so receivers fall back to their own
+            // eye animation instead of showing a stale or made up gaze.
+            if combined_eyes.l_tracking == EyeTrackingState::Lost
+                && combined_eyes.r_tracking == EyeTrackingState::Lost
+            {
+                continue;
+            }
+
+            let packets = Packets::encode(&combined_eyes, &connections, &profiles);
+
+            for connection in &mut connections {
+                let protocols = connection.destination.protocols;
+                for packet in packets.for_protocols(&protocols) {
+                    connection.send(packet).await;
+                }
             }
         }
     })
 }
+
+fn vrchat_native_packet(combined_eyes: &CombinedEyeGazeState) -> Vec<u8> {
+    encoder::encode(&OscPacket::Message(OscMessage {
+        addr: "/tracking/eye/LeftRightPitchYaw".to_string(),
+        args: vec![
+            OscType::Float(combined_eyes.l_pitch),
+            OscType::Float(combined_eyes.l_yaw),
+            OscType::Float(combined_eyes.r_pitch),
+            OscType::Float(combined_eyes.r_yaw),
+        ],
+    }))
+    .unwrap()
+}
+
+fn eyes_closed_packet(combined_eyes: &CombinedEyeGazeState) -> Vec<u8> {
+    let vrc_eyelids = (combined_eyes.l_closed + combined_eyes.r_closed) / 2.0;
+    encoder::encode(&OscPacket::Message(OscMessage {
+        addr: "/tracking/eye/EyesClosedAmount".to_string(),
+        args: vec![OscType::Float(vrc_eyelids)],
+    }))
+    .unwrap()
+}
+
+fn vrcft_v2_packet(combined_eyes: &CombinedEyeGazeState, profile: &OutputProfile) -> Vec<u8> {
+    const VRCFT_OSC_PREFIX: &str = "/avatar/parameters/FT/v2/";
+
+    let combined_eyes = profile.apply(combined_eyes);
+
+    let l_yaw_norm = combined_eyes.l_yaw.to_radians().sin();
+    let l_pitch_norm = combined_eyes.l_pitch.to_radians().sin();
+    let l_eyelid = VRCFT_EYELID_RELAXED * (1.0 - combined_eyes.l_closed)
+        + (1.0 - VRCFT_EYELID_RELAXED) * combined_eyes.l_wide;
+
+    let r_yaw_norm = combined_eyes.r_yaw.to_radians().sin();
+    let r_pitch_norm = combined_eyes.r_pitch.to_radians().sin();
+    let r_eyelid = VRCFT_EYELID_RELAXED * (1.0 - combined_eyes.r_closed)
+        + (1.0 - VRCFT_EYELID_RELAXED) * combined_eyes.r_wide;
+    let pitch_norm = ((combined_eyes.l_pitch + combined_eyes.r_pitch) / 2.0)
+        .to_radians()
+        .sin();
+    let pupil_dilation = (combined_eyes.l_pupil + combined_eyes.r_pupil) / 2.0;
+
+    encoder::encode(&OscPacket::Bundle(OscBundle {
+        timetag: SystemTime::now().try_into().unwrap(),
+        content: vec![
+            OscPacket::Message(OscMessage {
+                addr: concatcp!(VRCFT_OSC_PREFIX, "EyeY").to_string(),
+                args: vec![OscType::Float(-pitch_norm)],
+            }),
+            OscPacket::Message(OscMessage {
+                addr: concatcp!(VRCFT_OSC_PREFIX, "EyeLeftX").to_string(),
+                args: vec![OscType::Float(l_yaw_norm)],
+            }),
+            OscPacket::Message(OscMessage {
+                addr: concatcp!(VRCFT_OSC_PREFIX, "EyeLeftY").to_string(),
+                args: vec![OscType::Float(-l_pitch_norm)],
+            }),
+            OscPacket::Message(OscMessage {
+                addr: concatcp!(VRCFT_OSC_PREFIX, "EyeLidLeft").to_string(),
+                args: vec![OscType::Float(l_eyelid)],
+            }),
+            OscPacket::Message(OscMessage {
+                addr: concatcp!(VRCFT_OSC_PREFIX, "EyeRightX").to_string(),
+                args: vec![OscType::Float(r_yaw_norm)],
+            }),
+            OscPacket::Message(OscMessage {
+                addr: concatcp!(VRCFT_OSC_PREFIX, "EyeRightY").to_string(),
+                args: vec![OscType::Float(-r_pitch_norm)],
+            }),
+            OscPacket::Message(OscMessage {
+                addr: concatcp!(VRCFT_OSC_PREFIX, "EyeLidRight").to_string(),
+                args: vec![OscType::Float(r_eyelid)],
+            }),
+            OscPacket::Message(OscMessage {
+                addr: concatcp!(VRCFT_OSC_PREFIX, "EyeSquintLeft").to_string(),
+                args: vec![OscType::Float(combined_eyes.l_squint)],
+            }),
+            OscPacket::Message(OscMessage {
+                addr: concatcp!(VRCFT_OSC_PREFIX, "EyeSquintRight").to_string(),
+                args: vec![OscType::Float(combined_eyes.r_squint)],
+            }),
+            OscPacket::Message(OscMessage {
+                addr: concatcp!(VRCFT_OSC_PREFIX, "PupilDilation").to_string(),
+                args: vec![OscType::Float(pupil_dilation)],
+            }),
+            OscPacket::Message(OscMessage {
+                addr: concatcp!(VRCFT_OSC_PREFIX, "PupilDiameterLeft").to_string(),
+                args: vec![OscType::Float(combined_eyes.l_pupil)],
+            }),
+            OscPacket::Message(OscMessage {
+                addr: concatcp!(VRCFT_OSC_PREFIX, "PupilDiameterRight").to_string(),
+                args: vec![OscType::Float(combined_eyes.r_pupil)],
+            }),
+        ],
+    }))
+    .unwrap()
+}
+
+fn confidence_packet(combined_eyes: &CombinedEyeGazeState) -> Vec<u8> {
+    const CONFIDENCE_OSC_PREFIX: &str = "/avatar/parameters/ETVR/";
+
+    encoder::encode(&OscPacket::Bundle(OscBundle {
+        timetag: SystemTime::now().try_into().unwrap(),
+        content: vec![
+            OscPacket::Message(OscMessage {
+                addr: concatcp!(CONFIDENCE_OSC_PREFIX, "EyeConfidenceLeft").to_string(),
+                args: vec![OscType::Float(combined_eyes.l_confidence)],
+            }),
+            OscPacket::Message(OscMessage {
+                addr: concatcp!(CONFIDENCE_OSC_PREFIX, "EyeConfidenceRight").to_string(),
+                args: vec![OscType::Float(combined_eyes.r_confidence)],
+            }),
+        ],
+    }))
+    .unwrap()
+}
+
+fn focus_distance_packet(combined_eyes: &CombinedEyeGazeState) -> Vec<u8> {
+    encoder::encode(&OscPacket::Message(OscMessage {
+        addr: "/avatar/parameters/ETVR/FocusDistance".to_string(),
+        args: vec![OscType::Float(combined_eyes.focus_distance)],
+    }))
+    .unwrap()
+}
@@ -0,0 +1,130 @@
+use std::sync::Mutex;
+
+use crate::structs::CombinedEyeGazeState;
+
+/// Consumers of the combined gaze, each with its own quirks.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Output {
+    OscNative,
+    Vrcft,
+    OpenXrSocial,
+    OpenXrGaze,
+}
+
+impl Output {
+    pub const ALL: [Output; 4] = [
+        Output::OscNative,
+        Output::Vrcft,
+        Output::OpenXrSocial,
+        Output::OpenXrGaze,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Output::OscNative => "VRChat OSC",
+            Output::Vrcft => "VRCFT v2 OSC",
+            Output::OpenXrSocial => "OpenXR eye tracking social",
+            Output::OpenXrGaze => "OpenXR gaze interaction",
+        }
+    }
+}
+
+/// Post-processing of the angles for a single output, so one output's workaround
+/// doesn't degrade another.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputProfile {
+    /// Smallest yaw difference between the eyes, degrees.
+    pub min_vergence: f32,
+    pub pitch_scale: f32,
+    pub yaw_scale: f32,
+    pub invert_pitch: bool,
+    pub invert_yaw: bool,
+    /// Angles are clamped to plus minus these, degrees.
+    pub max_pitch: f32,
+    pub max_yaw: f32,
+}
+
+impl Default for OutputProfile {
+    fn default() -> Self {
+        Self {
+            min_vergence: 0.0,
+            pitch_scale: 1.0,
+            yaw_scale: 1.0,
+            invert_pitch: false,
+            invert_yaw: false,
+            max_pitch: 90.0,
+            max_yaw: 90.0,
+        }
+    }
+}
+
+impl OutputProfile {
+    pub fn for_output(output: Output) -> Self {
+        match output {
+            // Slightly nudge the eyes together. Otherwise Steam Link refuses
+            // to use eye tracking from the `XR_FB_eye_tracking_social` extension.
+            // Probably tries to calculate convergence distance.
+            Output::OpenXrSocial => Self {
+                min_vergence: 0.05,
+                ..Default::default()
+            },
+            Output::OscNative | Output::Vrcft | Output::OpenXrGaze => Self::default(),
+        }
+    }
+
+    /// Clamping isn't linear, so this goes after any prediction.
+    pub fn apply(&self, gaze: &CombinedEyeGazeState) -> CombinedEyeGazeState {
+        let avg_yaw = (gaze.l_yaw + gaze.r_yaw) / 2.0;
+        let half_diff = (gaze.l_yaw - gaze.r_yaw).max(self.min_vergence) / 2.0;
+
+        let pitch_factor = if self.invert_pitch {
+            -self.pitch_scale
+        } else {
+            self.pitch_scale
+        };
+        let yaw_factor = if self.invert_yaw {
+            -self.yaw_scale
+        } else {
+            self.yaw_scale
+        };
+        let pitch = |angle: f32| (angle * pitch_factor).clamp(-self.max_pitch, self.max_pitch);
+        let yaw = |angle: f32| (angle * yaw_factor).clamp(-self.max_yaw, self.max_yaw);
+
+        CombinedEyeGazeState {
+            pitch: pitch(gaze.pitch),
+            l_yaw: yaw(avg_yaw + half_diff),
+            r_yaw: yaw(avg_yaw - half_diff),
+            l_pitch: pitch(gaze.l_pitch),
+            r_pitch: pitch(gaze.r_pitch),
+            gaze_pitch: pitch(gaze.gaze_pitch),
+            gaze_yaw: yaw(gaze.gaze_yaw),
+            pitch_velocity: gaze.pitch_velocity * pitch_factor,
+            l_yaw_velocity: gaze.l_yaw_velocity * yaw_factor,
+            r_yaw_velocity: gaze.r_yaw_velocity * yaw_factor,
+            ..*gaze
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct OutputProfileControl {
+    profiles: Mutex<[OutputProfile; Output::ALL.len()]>,
+}
+
+impl Default for OutputProfileControl {
+    fn default() -> Self {
+        Self {
+            profiles: Mutex::new(Output::ALL.map(OutputProfile::for_output)),
+        }
+    }
+}
+
+impl OutputProfileControl {
+    pub fn profile(&self, output: Output) -> OutputProfile {
+        self.profiles.lock().unwrap()[output as usize]
+    }
+
+    pub fn set_profile(&self, output: Output, profile: OutputProfile) {
+        self.profiles.lock().unwrap()[output as usize] = profile;
+    }
+}
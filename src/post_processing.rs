@@ -0,0 +1,145 @@
+use std::sync::Mutex;
+
+use crate::structs::{CombinedEyeGazeState, EyeGazeState};
+
+/// When both eyes are forced to look in the same direction.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GazeLink {
+    Off,
+    /// When an eye isn't tracked, has a low confidence, or the eyes diverge.
+    WhenUnreliable,
+    Always,
+}
+
+impl GazeLink {
+    pub const ALL: [GazeLink; 3] = [GazeLink::Off, GazeLink::WhenUnreliable, GazeLink::Always];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            GazeLink::Off => "Off",
+            GazeLink::WhenUnreliable => "When unreliable",
+            GazeLink::Always => "Always",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PostProcessSettings {
+    /// Move the eyelids together while they are close, so unintended winks don't show.
+    pub eyelid_sync: bool,
+    /// Largest difference of the closed and wide amounts that is still synced.
+    pub eyelid_sync_threshold: f32,
+    pub gaze_link: GazeLink,
+    /// Eyes below this confidence make the vergence unreliable.
+    pub link_confidence: f32,
+    /// Eyes diverging more than this make the vergence unreliable, degrees.
+    pub max_divergence: f32,
+    /// Derive squint from the closed amount and remap wide past `neutral_range`. Off leaves
+    /// wide as calibrated and squint at zero.
+    pub squint_widen: bool,
+    /// Closed and wide amounts within this range around the calibrated neutral are
+    /// neither squint nor widen.
+    pub neutral_range: f32,
+    /// Closed amount at which the squint is full.
+    pub full_squint: f32,
+}
+
+impl Default for PostProcessSettings {
+    fn default() -> Self {
+        Self {
+            eyelid_sync: false,
+            eyelid_sync_threshold: 0.2,
+            gaze_link: GazeLink::Off,
+            link_confidence: 0.5,
+            max_divergence: 2.0,
+            squint_widen: false,
+            neutral_range: 0.1,
+            full_squint: 0.5,
+        }
+    }
+}
+
+impl PostProcessSettings {
+    /// Whether both eyes should look in one direction. `yaw_diff` is left minus right
+    /// yaw, positive when converging.
+    pub fn link_gaze(
+        &self,
+        both_tracked: bool,
+        l_confidence: f32,
+        r_confidence: f32,
+        yaw_diff: f32,
+    ) -> bool {
+        match self.gaze_link {
+            GazeLink::Off => false,
+            GazeLink::Always => true,
+            GazeLink::WhenUnreliable => {
+                !both_tracked
+                    || l_confidence.min(r_confidence) < self.link_confidence
+                    || yaw_diff < -self.max_divergence
+            }
+        }
+    }
+
+    /// Syncs the eyelids and, when enabled, derives squint and widen, from eyelids already
+    /// relative to the calibrated neutral of each eye.
+    pub fn apply_eyelids(&self, gaze: &mut CombinedEyeGazeState) {
+        gaze.eyelids_synced = self.eyelid_sync
+            && (gaze.l_closed - gaze.r_closed).abs() <= self.eyelid_sync_threshold
+            && (gaze.l_wide - gaze.r_wide).abs() <= self.eyelid_sync_threshold;
+
+        if gaze.eyelids_synced {
+            let eyelid = (gaze.l_eyelid + gaze.r_eyelid) / 2.0;
+            let closed = (gaze.l_closed + gaze.r_closed) / 2.0;
+            let wide = (gaze.l_wide + gaze.r_wide) / 2.0;
+            (gaze.l_eyelid, gaze.r_eyelid) = (eyelid, eyelid);
+            (gaze.l_closed, gaze.r_closed) = (closed, closed);
+            (gaze.l_wide, gaze.r_wide) = (wide, wide);
+        }
+
+        if !self.squint_widen {
+            return;
+        }
+
+        let beyond_neutral = |amount: f32, full: f32| {
+            let range = (full - self.neutral_range).max(f32::EPSILON);
+            ((amount - self.neutral_range) / range).clamp(0.0, 1.0)
+        };
+        gaze.l_squint = beyond_neutral(gaze.l_closed, self.full_squint);
+        gaze.r_squint = beyond_neutral(gaze.r_closed, self.full_squint);
+        gaze.l_wide = beyond_neutral(gaze.l_wide, 1.0);
+        gaze.r_wide = beyond_neutral(gaze.r_wide, 1.0);
+    }
+}
+
+/// Pitch and yaw both eyes look at when linked, weighted by confidence.
+pub fn linked_direction(l_state: &EyeGazeState, r_state: &EyeGazeState) -> (f32, f32) {
+    let l_weight = l_state.confidence.max(0.0);
+    let r_weight = r_state.confidence.max(0.0);
+    let total = l_weight + r_weight;
+    if total <= 0.0 {
+        return (
+            (l_state.pitch + r_state.pitch) / 2.0,
+            (l_state.yaw + r_state.yaw) / 2.0,
+        );
+    }
+
+    (
+        (l_state.pitch * l_weight + r_state.pitch * r_weight) / total,
+        (l_state.yaw * l_weight + r_state.yaw * r_weight) / total,
+    )
+}
+
+#[derive(Debug, Default)]
+pub struct PostProcessControl {
+    settings: Mutex<PostProcessSettings>,
+}
+
+impl PostProcessControl {
+    pub fn settings(&self) -> PostProcessSettings {
+        *self.settings.lock().unwrap()
+    }
+
+    pub fn set_settings(&self, settings: PostProcessSettings) {
+        *self.settings.lock().unwrap() = settings;
+    }
+}
@@ -0,0 +1,117 @@
+use crate::structs::{EyeGazeState, MAX_PREDICTION, Timestamp, prediction_confidence};
+
+/// Constant velocity Kalman filter of a single angle.
+#[derive(Clone, Copy, Debug)]
+struct AxisKalman {
+    angle: f32,
+    velocity: f32,
+    // Covariance of (angle, velocity).
+    p: [[f32; 2]; 2],
+}
+
+impl AxisKalman {
+    /// White noise acceleration, (deg/s^2)^2 * s. Large, eyes move in jumps.
+    const PROCESS_NOISE: f32 = 20000.0;
+    /// Measurement variance, deg^2.
+    const MEASUREMENT_NOISE: f32 = 0.25;
+    /// Initial velocity variance, (deg/s)^2.
+    const INITIAL_VELOCITY_VARIANCE: f32 = 100.0;
+
+    fn new(angle: f32) -> Self {
+        Self {
+            angle,
+            velocity: 0.0,
+            p: [
+                [Self::MEASUREMENT_NOISE, 0.0],
+                [0.0, Self::INITIAL_VELOCITY_VARIANCE],
+            ],
+        }
+    }
+
+    fn update(&mut self, measurement: f32, dt: f32) {
+        // Predict.
+        self.angle += self.velocity * dt;
+        let [[p00, p01], [p10, p11]] = self.p;
+        let q = Self::PROCESS_NOISE;
+        let p00 = p00 + dt * (p10 + p01) + dt * dt * p11 + q * dt.powi(3) / 3.0;
+        let p01 = p01 + dt * p11 + q * dt * dt / 2.0;
+        let p10 = p10 + dt * p11 + q * dt * dt / 2.0;
+        let p11 = p11 + q * dt;
+
+        // Correct.
+        let innovation = measurement - self.angle;
+        let s = p00 + Self::MEASUREMENT_NOISE;
+        let (k0, k1) = (p00 / s, p10 / s);
+
+        self.angle += k0 * innovation;
+        self.velocity += k1 * innovation;
+        self.p = [
+            [(1.0 - k0) * p00, (1.0 - k0) * p01],
+            [p10 - k1 * p00, p11 - k1 * p01],
+        ];
+    }
+}
+
+/// Motion model of one eye, used to extrapolate the gaze to a different time.
+#[derive(Debug, Default)]
+pub struct EyePredictor {
+    last: Option<(EyeGazeState, Timestamp, AxisKalman, AxisKalman)>,
+}
+
+impl EyePredictor {
+    /// Samples further apart than this restart the model instead of updating it.
+    const RESET_GAP_SECS: f32 = 0.25;
+
+    /// Only valid samples move the model.
+    pub fn update(&mut self, state: &EyeGazeState, timestamp: Timestamp) {
+        if !state.valid {
+            return;
+        }
+
+        let (pitch, yaw) = match self.last {
+            Some((_, last_timestamp, mut pitch, mut yaw)) => {
+                let dt = timestamp
+                    .duration_since(last_timestamp)
+                    .unwrap_or_default()
+                    .as_secs_f32();
+
+                if dt > Self::RESET_GAP_SECS {
+                    (AxisKalman::new(state.pitch), AxisKalman::new(state.yaw))
+                } else {
+                    pitch.update(state.pitch, dt);
+                    yaw.update(state.yaw, dt);
+                    (pitch, yaw)
+                }
+            }
+            None => (AxisKalman::new(state.pitch), AxisKalman::new(state.yaw)),
+        };
+
+        self.last = Some((*state, timestamp, pitch, yaw));
+    }
+
+    /// Pitch and yaw velocity in degrees per second.
+    pub fn velocity(&self) -> (f32, f32) {
+        self.last
+            .map(|(_, _, pitch, yaw)| (pitch.velocity, yaw.velocity))
+            .unwrap_or_default()
+    }
+
+    /// Extrapolates the last sample to `timestamp`, no further than `MAX_PREDICTION`.
+    /// Values other than the angles are kept, the confidence decays with the horizon.
+    pub fn predict(&self, timestamp: Timestamp) -> Option<EyeGazeState> {
+        let (state, last_timestamp, pitch, yaw) = self.last?;
+
+        let horizon = timestamp
+            .duration_since(last_timestamp)
+            .unwrap_or_default()
+            .min(MAX_PREDICTION);
+        let dt = horizon.as_secs_f32();
+
+        Some(EyeGazeState {
+            pitch: pitch.angle + pitch.velocity * dt,
+            yaw: yaw.angle + yaw.velocity * dt,
+            confidence: state.confidence * prediction_confidence(horizon),
+            ..state
+        })
+    }
+}
@@ -0,0 +1,312 @@
+use image::{GrayImage, Luma};
+
+/// Optional image normalization applied to the model input, to make different IR
+/// illumination setups look closer to what the model was trained on.
+/// The steps run in the order of the fields.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PreprocessingSettings {
+    /// Brightens the edges to compensate for LED/lens falloff.
+    pub vignette_correction: bool,
+    /// Gain at the corners is `1 + vignette_strength`.
+    pub vignette_strength: f32,
+
+    pub denoise: bool,
+    /// Gaussian blur sigma in model input pixels.
+    pub denoise_sigma: f32,
+
+    /// Stretches the 1st..99th percentile to the full range.
+    pub auto_gain: bool,
+
+    pub equalization: Equalization,
+    /// CLAHE contrast limit, relative to the average histogram bin.
+    pub clahe_clip_limit: f32,
+    /// CLAHE tiles along each side of the image.
+    pub clahe_tiles: u32,
+
+    pub gamma_correction: bool,
+    /// Output is `input ^ gamma`, values below 1 brighten the image.
+    pub gamma: f32,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Equalization {
+    #[default]
+    None,
+    Histogram,
+    Clahe,
+}
+
+impl Default for PreprocessingSettings {
+    fn default() -> Self {
+        Self {
+            vignette_correction: false,
+            vignette_strength: 0.5,
+
+            denoise: false,
+            denoise_sigma: 0.7,
+
+            auto_gain: false,
+
+            equalization: Equalization::None,
+            clahe_clip_limit: 2.0,
+            clahe_tiles: 4,
+
+            gamma_correction: false,
+            gamma: 1.0,
+        }
+    }
+}
+
+impl PreprocessingSettings {
+    pub fn is_noop(&self) -> bool {
+        !self.vignette_correction
+            && !self.denoise
+            && !self.auto_gain
+            && self.equalization == Equalization::None
+            && !self.gamma_correction
+    }
+}
+
+pub fn preprocess(image: &mut GrayImage, settings: &PreprocessingSettings) {
+    if settings.is_noop() {
+        return;
+    }
+
+    if settings.vignette_correction {
+        correct_vignette(image, settings.vignette_strength);
+    }
+
+    if settings.denoise && settings.denoise_sigma > 0.0 {
+        *image = image::imageops::blur(image, settings.denoise_sigma);
+    }
+
+    if settings.auto_gain {
+        auto_gain(image);
+    }
+
+    match settings.equalization {
+        Equalization::None => {}
+        Equalization::Histogram => equalize(image, 1, f32::INFINITY),
+        Equalization::Clahe => {
+            equalize(image, settings.clahe_tiles.max(1), settings.clahe_clip_limit);
+        }
+    }
+
+    if settings.gamma_correction && settings.gamma > 0.0 {
+        let lut: [u8; 256] = std::array::from_fn(|i| {
+            ((i as f32 / 255.0).powf(settings.gamma) * 255.0).round() as u8
+        });
+        apply_lut(image, &lut);
+    }
+}
+
+fn apply_lut(image: &mut GrayImage, lut: &[u8; 256]) {
+    for pixel in image.pixels_mut() {
+        pixel.0[0] = lut[pixel.0[0] as usize];
+    }
+}
+
+fn correct_vignette(image: &mut GrayImage, strength: f32) {
+    let (w, h) = image.dimensions();
+    let (cx, cy) = (w as f32 / 2.0, h as f32 / 2.0);
+    let max_dist_sq = cx * cx + cy * cy;
+
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let (dx, dy) = (x as f32 + 0.5 - cx, y as f32 + 0.5 - cy);
+        let gain = 1.0 + strength * (dx * dx + dy * dy) / max_dist_sq;
+        pixel.0[0] = (pixel.0[0] as f32 * gain).clamp(0.0, 255.0) as u8;
+    }
+}
+
+fn auto_gain(image: &mut GrayImage) {
+    const LOW_PERCENTILE: f32 = 0.01;
+    const HIGH_PERCENTILE: f32 = 0.99;
+
+    let mut histogram = [0u32; 256];
+    for Luma([p]) in image.pixels() {
+        histogram[*p as usize] += 1;
+    }
+
+    let count = (image.width() * image.height()) as f32;
+    let percentile = |p: f32| {
+        let target = (count * p) as u32;
+        let mut sum = 0;
+        histogram
+            .iter()
+            .position(|&bin| {
+                sum += bin;
+                sum > target
+            })
+            .unwrap_or(255) as f32
+    };
+
+    let low = percentile(LOW_PERCENTILE);
+    let high = percentile(HIGH_PERCENTILE);
+    if high <= low {
+        return;
+    }
+
+    let lut: [u8; 256] = std::array::from_fn(|i| {
+        ((i as f32 - low) * 255.0 / (high - low)).clamp(0.0, 255.0) as u8
+    });
+    apply_lut(image, &lut);
+}
+
+/// Histogram equalization over `tiles`x`tiles` regions, interpolated between the
+/// tile centers. One tile with no clip limit is the plain global equalization.
+fn equalize(image: &mut GrayImage, tiles: u32, clip_limit: f32) {
+    let (w, h) = image.dimensions();
+    let tile_w = w.div_ceil(tiles);
+    let tile_h = h.div_ceil(tiles);
+
+    let mut luts = vec![[0u8; 256]; (tiles * tiles) as usize];
+
+    for ty in 0..tiles {
+        for tx in 0..tiles {
+            // In f32, so small tiles don't lose the clipped excess to integer division.
+            let mut histogram = [0f32; 256];
+            let mut count = 0.0;
+            for y in ty * tile_h..((ty + 1) * tile_h).min(h) {
+                for x in tx * tile_w..((tx + 1) * tile_w).min(w) {
+                    histogram[image.get_pixel(x, y).0[0] as usize] += 1.0;
+                    count += 1.0;
+                }
+            }
+
+            let lut = &mut luts[(ty * tiles + tx) as usize];
+            if count == 0.0 {
+                *lut = std::array::from_fn(|i| i as u8);
+                continue;
+            }
+
+            // Clip the histogram to limit noise amplification and spread the excess evenly.
+            let limit = clip_limit * count / 256.0;
+            let mut excess = 0.0;
+            for bin in histogram.iter_mut() {
+                if *bin > limit {
+                    excess += *bin - limit;
+                    *bin = limit;
+                }
+            }
+            for bin in histogram.iter_mut() {
+                *bin += excess / 256.0;
+            }
+
+            let mut cdf = 0.0;
+            for (i, bin) in histogram.iter().enumerate() {
+                cdf += bin;
+                lut[i] = (cdf * 255.0 / count).clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+
+    // Position between the two nearest tile centers along one axis.
+    let neighbours = |pos: u32, tile_size: u32| {
+        let f = (pos as f32 + 0.5) / tile_size as f32 - 0.5;
+        let t0 = (f.floor().max(0.0) as u32).min(tiles - 1);
+        let t1 = (t0 + 1).min(tiles - 1);
+        (t0, t1, (f - t0 as f32).clamp(0.0, 1.0))
+    };
+
+    for (x, y, pixel) in image.enumerate_pixels_mut() {
+        let p = pixel.0[0] as usize;
+        let (tx0, tx1, ax) = neighbours(x, tile_w);
+        let (ty0, ty1, ay) = neighbours(y, tile_h);
+
+        let lut = |tx: u32, ty: u32| luts[(ty * tiles + tx) as usize][p] as f32;
+        let top = lut(tx0, ty0) * (1.0 - ax) + lut(tx1, ty0) * ax;
+        let bottom = lut(tx0, ty1) * (1.0 - ax) + lut(tx1, ty1) * ax;
+
+        pixel.0[0] = (top * (1.0 - ay) + bottom * ay).round() as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 16x16 image with 32 levels from 100 to 131, each on 8 pixels.
+    fn ramp() -> GrayImage {
+        GrayImage::from_fn(16, 16, |x, y| Luma([100 + ((y * 16 + x) / 8) as u8]))
+    }
+
+    fn range(image: &GrayImage) -> (u8, u8) {
+        image
+            .pixels()
+            .fold((u8::MAX, u8::MIN), |(min, max), Luma([p])| {
+                (min.min(*p), max.max(*p))
+            })
+    }
+
+    #[test]
+    fn noop_keeps_image() {
+        let mut image = ramp();
+        preprocess(&mut image, &PreprocessingSettings::default());
+        assert_eq!(image, ramp());
+    }
+
+    #[test]
+    fn auto_gain_stretches_to_full_range() {
+        let mut image = ramp();
+        auto_gain(&mut image);
+        assert_eq!(range(&image), (0, 255));
+    }
+
+    #[test]
+    fn equalization_keeps_order() {
+        let input = ramp();
+        let mut image = input.clone();
+        equalize(&mut image, 1, f32::INFINITY);
+
+        let mut pairs = input
+            .pixels()
+            .zip(image.pixels())
+            .map(|(Luma([a]), Luma([b]))| (*a, *b))
+            .collect::<Vec<_>>();
+        pairs.sort();
+        assert!(pairs.windows(2).all(|w| w[0].1 <= w[1].1));
+        assert_eq!(range(&image).1, 255);
+    }
+
+    #[test]
+    fn clahe_keeps_flat_image_flat() {
+        let mut image = GrayImage::from_pixel(32, 32, Luma([100]));
+        equalize(&mut image, 4, 2.0);
+        let (min, max) = range(&image);
+        assert_eq!(min, max);
+        assert!(min.abs_diff(100) <= 3, "flat 100 became {min}");
+    }
+
+    #[test]
+    fn gamma_brightens_midtones() {
+        let mut image = GrayImage::from_pixel(4, 4, Luma([64]));
+        let settings = PreprocessingSettings {
+            gamma_correction: true,
+            gamma: 0.5,
+            ..Default::default()
+        };
+        preprocess(&mut image, &settings);
+        assert_eq!(image.get_pixel(0, 0)[0], 128);
+    }
+
+    #[test]
+    fn vignette_brightens_corners_only() {
+        let mut image = GrayImage::from_pixel(64, 64, Luma([100]));
+        correct_vignette(&mut image, 0.5);
+        assert_eq!(image.get_pixel(32, 32)[0], 100);
+        assert!(image.get_pixel(0, 0)[0] > 140);
+    }
+
+    #[test]
+    fn denoise_spreads_a_hot_pixel() {
+        let mut image = GrayImage::from_pixel(9, 9, Luma([0]));
+        image.put_pixel(4, 4, Luma([255]));
+        let settings = PreprocessingSettings {
+            denoise: true,
+            ..Default::default()
+        };
+        preprocess(&mut image, &settings);
+        assert!(image.get_pixel(4, 4)[0] < 255);
+        assert!(image.get_pixel(5, 4)[0] > 0);
+    }
+}
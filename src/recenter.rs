@@ -0,0 +1,152 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::structs::{Eye, EyeGazeState, Timestamp};
+
+/// Slower samples count towards the fixation statistics, deg/s.
+const FIXATION_VELOCITY: f32 = 30.0;
+/// Samples further apart than this don't give a velocity.
+const MAX_SAMPLE_GAP: Duration = Duration::from_millis(100);
+/// Long-term fixation means closer to zero than this aren't corrected, degrees.
+const DRIFT_DEADZONE: f32 = 0.5;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RecenterSettings {
+    /// Slowly move the zero so the long-term mean of fixations is straight ahead.
+    pub auto_drift: bool,
+    /// Time constant of the fixation mean, seconds.
+    pub drift_window: f32,
+    /// Fastest auto correction, degrees per minute.
+    pub max_drift_rate: f32,
+}
+
+impl Default for RecenterSettings {
+    fn default() -> Self {
+        Self {
+            auto_drift: false,
+            drift_window: 60.0,
+            max_drift_rate: 1.0,
+        }
+    }
+}
+
+/// Zero offset of one eye and its drift statistics.
+#[derive(Clone, Copy, Debug, Default)]
+struct EyeRecenter {
+    /// Gaze that is treated as straight ahead, degrees.
+    offset: (f32, f32),
+    /// Capture the next sample as the new zero.
+    pending: bool,
+    last: Option<(Timestamp, (f32, f32))>,
+    /// Mean of the recentered fixation gaze.
+    fixation_mean: Option<(f32, f32)>,
+}
+
+impl EyeRecenter {
+    fn drift(&mut self, gaze: (f32, f32), timestamp: Timestamp, settings: &RecenterSettings) {
+        let dt = match self.last.replace((timestamp, gaze)) {
+            Some((last_timestamp, last_gaze)) => {
+                let dt = timestamp.duration_since(last_timestamp).unwrap_or_default();
+                let velocity = (gaze.0 - last_gaze.0).hypot(gaze.1 - last_gaze.1)
+                    / dt.as_secs_f32().max(0.001);
+                if dt > MAX_SAMPLE_GAP || velocity > FIXATION_VELOCITY {
+                    return;
+                }
+                dt.as_secs_f32()
+            }
+            None => return,
+        };
+
+        let alpha = 1.0 - (-dt / settings.drift_window.max(f32::EPSILON)).exp();
+        let (mean_pitch, mean_yaw) = match self.fixation_mean {
+            Some((pitch, yaw)) => (
+                pitch + (gaze.0 - pitch) * alpha,
+                yaw + (gaze.1 - yaw) * alpha,
+            ),
+            None => gaze,
+        };
+
+        let max_step = settings.max_drift_rate / 60.0 * dt;
+        let step = |mean: f32| {
+            if mean.abs() <= DRIFT_DEADZONE {
+                0.0
+            } else {
+                mean.clamp(-max_step, max_step)
+            }
+        };
+        let (pitch_step, yaw_step) = (step(mean_pitch), step(mean_yaw));
+        self.offset.0 += pitch_step;
+        self.offset.1 += yaw_step;
+        // The mean is of recentered gaze, it moves with the zero or the correction overshoots.
+        self.fixation_mean = Some((mean_pitch - pitch_step, mean_yaw - yaw_step));
+    }
+}
+
+/// Per-eye gaze zero against headset slip, shared with the UI, OSC, OpenXR and the
+/// control socket.
+#[derive(Debug, Default)]
+pub struct RecenterControl {
+    eyes: Mutex<[EyeRecenter; 2]>,
+    settings: Mutex<RecenterSettings>,
+}
+
+impl RecenterControl {
+    pub fn settings(&self) -> RecenterSettings {
+        *self.settings.lock().unwrap()
+    }
+
+    pub fn set_settings(&self, settings: RecenterSettings) {
+        *self.settings.lock().unwrap() = settings;
+    }
+
+    /// The next good sample of each eye becomes its new zero, the user should look
+    /// straight ahead.
+    pub fn request(&self) {
+        for eye in self.eyes.lock().unwrap().iter_mut() {
+            eye.pending = true;
+        }
+    }
+
+    pub fn reset(&self) {
+        *self.eyes.lock().unwrap() = Default::default();
+    }
+
+    pub fn reset_eye(&self, eye: Eye) {
+        self.eyes.lock().unwrap()[eye as usize] = Default::default();
+    }
+
+    /// Current zero of an eye, pitch and yaw in degrees.
+    pub fn offset(&self, eye: Eye) -> (f32, f32) {
+        self.eyes.lock().unwrap()[eye as usize].offset
+    }
+
+    pub fn apply(&self, eye: Eye, state: EyeGazeState, timestamp: Timestamp) -> EyeGazeState {
+        if !state.valid {
+            return state;
+        }
+
+        let settings = self.settings();
+        let mut eyes = self.eyes.lock().unwrap();
+        let recenter = &mut eyes[eye as usize];
+
+        if recenter.pending {
+            recenter.offset = (state.pitch, state.yaw);
+            recenter.pending = false;
+            recenter.fixation_mean = None;
+        }
+
+        let gaze = (
+            state.pitch - recenter.offset.0,
+            state.yaw - recenter.offset.1,
+        );
+        if settings.auto_drift {
+            recenter.drift(gaze, timestamp, &settings);
+        }
+
+        EyeGazeState {
+            pitch: gaze.0,
+            yaw: gaze.1,
+            ..state
+        }
+    }
+}
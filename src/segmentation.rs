@@ -0,0 +1,272 @@
+// Support for models that output pupil/iris/sclera segmentation masks instead of
+// regressed angles. Ellipses are fitted to the mask and mapped to `EyeGazeState`.
+
+use image::{GrayImage, Luma};
+use log::warn;
+
+use crate::structs::EyeGazeState;
+
+/// Pupils smaller than this many mask pixels are treated as not found.
+const MIN_PUPIL_PIXELS: usize = 4;
+
+/// Classes as stored in the mask passed around, independent of the model channel order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum SegmentationClass {
+    Background = 0,
+    Sclera = 1,
+    Iris = 2,
+    Pupil = 3,
+}
+
+impl SegmentationClass {
+    fn from_name(name: &str) -> Self {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "sclera" => Self::Sclera,
+            "iris" => Self::Iris,
+            "pupil" => Self::Pupil,
+            _ => Self::Background,
+        }
+    }
+
+    pub fn from_mask_value(value: u8) -> Self {
+        match value {
+            1 => Self::Sclera,
+            2 => Self::Iris,
+            3 => Self::Pupil,
+            _ => Self::Background,
+        }
+    }
+}
+
+/// Read from the `segmentation_classes` model metadata, e.g. `background,sclera,iris,pupil`,
+/// with the classes in channel order. The output can be logits or probabilities.
+#[derive(Clone, Debug)]
+pub struct SegmentationLayout {
+    pub classes: Vec<SegmentationClass>,
+}
+
+impl SegmentationLayout {
+    pub fn from_metadata(classes: &str) -> Option<Self> {
+        let classes = classes
+            .split(',')
+            .map(SegmentationClass::from_name)
+            .collect::<Vec<_>>();
+
+        if !classes.contains(&SegmentationClass::Pupil) {
+            warn!("Segmentation model without a pupil class, ignoring");
+            return None;
+        }
+
+        Some(Self { classes })
+    }
+
+    /// Converts the output tensor, either NCHW or NHWC, into a class mask and the
+    /// probability of the chosen class per pixel.
+    pub fn decode(&self, shape: &[i64], data: &[f32]) -> Option<(GrayImage, Vec<f32>)> {
+        let channels = self.classes.len();
+        let &[1, d1, d2, d3] = shape else {
+            return None;
+        };
+        let (d1, d2, d3) = (d1 as usize, d2 as usize, d3 as usize);
+
+        let (h, w, index): (usize, usize, Box<dyn Fn(usize, usize, usize) -> usize>) =
+            if d1 == channels {
+                (d2, d3, Box::new(move |x, y, c| (c * d2 + y) * d3 + x))
+            } else if d3 == channels {
+                (d1, d2, Box::new(move |x, y, c| (y * d2 + x) * channels + c))
+            } else {
+                return None;
+            };
+
+        if data.len() != h * w * channels {
+            return None;
+        }
+
+        let mut probabilities = Vec::with_capacity(w * h);
+        let mask = GrayImage::from_fn(w as u32, h as u32, |x, y| {
+            let (x, y) = (x as usize, y as usize);
+
+            // Softmax over the classes of the pixel, only the winner is kept.
+            let values = (0..channels).map(|c| data[index(x, y, c)]);
+            let max = values.clone().fold(f32::NEG_INFINITY, f32::max);
+            let sum = values.clone().map(|v| (v - max).exp()).sum::<f32>();
+            let (class, _) = values
+                .enumerate()
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap_or((0, 0.0));
+
+            probabilities.push(1.0 / sum);
+            Luma([self.classes[class] as u8])
+        });
+
+        Some((mask, probabilities))
+    }
+}
+
+/// Ellipse with the same second moments as a set of pixels.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Ellipse {
+    pub center: [f32; 2],
+    /// Semi-axes, major first.
+    pub axes: [f32; 2],
+    /// Angle of the major axis, radians.
+    pub angle: f32,
+}
+
+impl Ellipse {
+    pub fn fit(points: &[[f32; 2]]) -> Option<Self> {
+        if points.is_empty() {
+            return None;
+        }
+
+        let n = points.len() as f32;
+        let cx = points.iter().map(|p| p[0]).sum::<f32>() / n;
+        let cy = points.iter().map(|p| p[1]).sum::<f32>() / n;
+
+        let (mut xx, mut yy, mut xy) = (0.0, 0.0, 0.0);
+        for [x, y] in points {
+            let (dx, dy) = (x - cx, y - cy);
+            xx += dx * dx;
+            yy += dy * dy;
+            xy += dx * dy;
+        }
+        let (xx, yy, xy) = (xx / n, yy / n, xy / n);
+
+        let mean = (xx + yy) / 2.0;
+        let spread = (((xx - yy) / 2.0).powi(2) + xy * xy).sqrt();
+
+        // A filled ellipse with semi-axis `a` has a variance of `a^2 / 4` along it.
+        Some(Ellipse {
+            center: [cx, cy],
+            axes: [
+                2.0 * (mean + spread).sqrt(),
+                2.0 * (mean - spread).max(0.0).sqrt(),
+            ],
+            angle: 0.5 * (2.0 * xy).atan2(xx - yy),
+        })
+    }
+
+    pub fn area(&self) -> f32 {
+        std::f32::consts::PI * self.axes[0] * self.axes[1]
+    }
+}
+
+/// Measurements taken from a mask, in mask sized units.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct EyeFeatures {
+    /// Pupil center, -1..1 over the mask with y pointing down.
+    pub pupil_center: Option<[f32; 2]>,
+    /// Fraction of the mask covered by the pupil, like `estimate_pupil_area`.
+    pub pupil_area: f32,
+    /// Height of the visible eye relative to the mask height.
+    pub aperture: f32,
+    pub confidence: f32,
+}
+
+impl EyeFeatures {
+    pub fn from_mask(mask: &GrayImage, probabilities: &[f32]) -> Self {
+        let (w, h) = mask.dimensions();
+
+        let mut pupil = Vec::new();
+        let mut pupil_probability = 0.0;
+        let mut visible = Vec::new();
+
+        for (x, y, Luma([value])) in mask.enumerate_pixels() {
+            let point = [x as f32 + 0.5, y as f32 + 0.5];
+            match SegmentationClass::from_mask_value(*value) {
+                SegmentationClass::Background => continue,
+                SegmentationClass::Pupil => {
+                    pupil.push(point);
+                    pupil_probability += probabilities[(y * w + x) as usize];
+                }
+                _ => {}
+            }
+            visible.push(point);
+        }
+
+        let aperture = Ellipse::fit(&visible)
+            .map(|eye| {
+                // Vertical extent of the fitted ellipse.
+                let (sin, cos) = eye.angle.sin_cos();
+                let half_height =
+                    ((eye.axes[0] * sin).powi(2) + (eye.axes[1] * cos).powi(2)).sqrt();
+                2.0 * half_height / h as f32
+            })
+            .unwrap_or(0.0);
+
+        let pupil_ellipse = Ellipse::fit(&pupil).filter(|_| pupil.len() >= MIN_PUPIL_PIXELS);
+
+        let Some(pupil_ellipse) = pupil_ellipse else {
+            return EyeFeatures {
+                pupil_center: None,
+                pupil_area: 0.0,
+                aperture,
+                confidence: 0.0,
+            };
+        };
+
+        // A clean mask fills its ellipse, fragmented or smeared ones don't.
+        let fill = (pupil.len() as f32 / pupil_ellipse.area().max(1.0)).clamp(0.0, 1.0);
+        let probability = pupil_probability / pupil.len() as f32;
+
+        EyeFeatures {
+            pupil_center: Some([
+                pupil_ellipse.center[0] / w as f32 * 2.0 - 1.0,
+                pupil_ellipse.center[1] / h as f32 * 2.0 - 1.0,
+            ]),
+            pupil_area: pupil.len() as f32 / (w * h) as f32,
+            aperture,
+            confidence: (probability * fill).clamp(0.0, 1.0),
+        }
+    }
+}
+
+/// Per eye calibration from mask measurements to gaze angles and eyelid openness.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SegmentationMapping {
+    /// Pupil center when looking straight ahead.
+    pub center: [f32; 2],
+    /// Degrees of yaw and pitch per unit of pupil movement.
+    pub gain: [f32; 2],
+    /// Aperture of a closed eye, maps to eyelid 0.
+    pub aperture_closed: f32,
+    /// Aperture of a wide open eye, maps to eyelid 1.
+    pub aperture_open: f32,
+}
+
+impl Default for SegmentationMapping {
+    fn default() -> Self {
+        Self {
+            center: [0.0, 0.0],
+            gain: [45.0, 45.0],
+            aperture_closed: 0.05,
+            aperture_open: 0.6,
+        }
+    }
+}
+
+impl SegmentationMapping {
+    /// Features come from the flipped right eye, so the yaw is mirrored back like for
+    /// regressor outputs. Without a pupil the sample is marked invalid.
+    pub fn gaze_state(&self, features: &EyeFeatures, is_left: bool) -> EyeGazeState {
+        let (pitch, yaw) = match features.pupil_center {
+            Some([x, y]) => (
+                -(y - self.center[1]) * self.gain[1],
+                (x - self.center[0]) * self.gain[0],
+            ),
+            None => (0.0, 0.0),
+        };
+
+        let aperture_range = (self.aperture_open - self.aperture_closed).max(f32::EPSILON);
+
+        EyeGazeState {
+            pitch,
+            yaw: yaw * if is_left { 1.0 } else { -1.0 },
+            eyelid: ((features.aperture - self.aperture_closed) / aperture_range).clamp(0.0, 1.0),
+            confidence: features.confidence,
+            pupil: features.pupil_area,
+            valid: features.pupil_center.is_some(),
+        }
+    }
+}
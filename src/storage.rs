@@ -0,0 +1,57 @@
+use std::path::PathBuf;
+
+#[cfg(target_os = "android")]
+use log::warn;
+
+/// Directory for user provided files (e.g. models) and persisted state.
+pub fn data_dir() -> PathBuf {
+    #[cfg(target_os = "android")]
+    {
+        match android_external_files_dir() {
+            Ok(dir) => dir,
+            Err(err) => {
+                warn!("Failed to get external files dir, using temp dir: {err:?}");
+                std::env::temp_dir()
+            }
+        }
+    }
+
+    #[cfg(not(target_os = "android"))]
+    {
+        PathBuf::from(".")
+    }
+}
+
+/// `Context.getExternalFilesDir(null)` of the app we're running in,
+/// e.g. `/sdcard/Android/data/<package>/files`. Accessible over adb/MTP without root.
+#[cfg(target_os = "android")]
+fn android_external_files_dir() -> Result<PathBuf, jni::errors::Error> {
+    use jni::{
+        JavaVM,
+        objects::{JObject, JString, JValue},
+    };
+
+    let context = ndk_context::android_context();
+    let vm = unsafe { JavaVM::from_raw(context.vm().cast()) }?;
+    let mut env = vm.attach_current_thread()?;
+    let context = unsafe { JObject::from_raw(context.context().cast()) };
+
+    let dir = env
+        .call_method(
+            &context,
+            "getExternalFilesDir",
+            "(Ljava/lang/String;)Ljava/io/File;",
+            &[JValue::Object(&JObject::null())],
+        )?
+        .l()?;
+    if dir.is_null() {
+        return Err(jni::errors::Error::NullPtr("getExternalFilesDir"));
+    }
+
+    let path = env
+        .call_method(&dir, "getAbsolutePath", "()Ljava/lang/String;", &[])?
+        .l()?;
+    let path: String = env.get_string(&JString::from(path))?.into();
+
+    Ok(PathBuf::from(path))
+}
@@ -1,21 +1,52 @@
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
 use image::{GenericImageView, SubImage};
 
 use crate::camera::Frame;
 
-const EYELID_NEUTRAL_VALUE: f32 = 0.75;
+/// Eyelid output of the models for a relaxed open eye, used until the user calibrates.
+pub const EYELID_NEUTRAL_VALUE: f32 = 0.75;
 
 // The plan is to make it possible for this be different for standalone/OpenXR/SteamVR builds.
 pub type Timestamp = SystemTime;
 pub const ZERO_TIMESTAMP: SystemTime = SystemTime::UNIX_EPOCH;
 
+/// Median adult interpupillary distance, meters.
+pub const DEFAULT_IPD: f32 = 0.063;
+/// Focus distance of parallel eyes, meters.
+pub const DEFAULT_FOCUS_DISTANCE: f32 = 10.0;
+
+/// Gaze is never extrapolated further than this from the last sample.
+pub const MAX_PREDICTION: Duration = Duration::from_millis(100);
+
+/// Confidence multiplier of a prediction `horizon` ahead of the sample.
+pub fn prediction_confidence(horizon: Duration) -> f32 {
+    const CONFIDENCE_DECAY_SECS: f32 = 0.2;
+    (-horizon.as_secs_f32() / CONFIDENCE_DECAY_SECS).exp()
+}
+
+/// How trustworthy the output of an eye is.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EyeTrackingState {
+    Tracking,
+    /// Short dropout or rejected samples, the values come from a fallback.
+    Degraded,
+    /// No good samples for a while, or never.
+    Lost,
+}
+
 /// Single eye state.
 #[derive(Copy, Clone, Debug)]
 pub struct EyeGazeState {
     pub pitch: f32,
     pub yaw: f32,
     pub eyelid: f32,
+    /// How much the estimator trusts this sample, 0.0..=1.0.
+    pub confidence: f32,
+    /// Raw pupil size in estimator units, not comparable between users or models.
+    pub pupil: f32,
+    /// False if the sample was rejected, the values are then held from the last good one.
+    pub valid: bool,
 }
 
 impl Default for EyeGazeState {
@@ -24,6 +55,9 @@ impl Default for EyeGazeState {
             pitch: 0.0,
             yaw: 0.0,
             eyelid: EYELID_NEUTRAL_VALUE,
+            confidence: 0.0,
+            pupil: 0.0,
+            valid: false,
         }
     }
 }
@@ -56,14 +90,59 @@ pub struct CombinedEyeGazeState {
     pub pitch: f32,
     pub l_yaw: f32,
     pub r_yaw: f32,
+    // Per eye pitch, equal to `pitch` unless independent pitch is enabled.
+    pub l_pitch: f32,
+    pub r_pitch: f32,
     pub l_eyelid: f32,
     pub r_eyelid: f32,
 
+    // Eyelid relative to the calibrated neutral of each eye, 0.0..=1.0 towards closed
+    // or towards wide open. At most one of them is non-zero.
+    pub l_closed: f32,
+    pub r_closed: f32,
+    pub l_wide: f32,
+    pub r_wide: f32,
+    // Partially closed eyelid beyond the neutral range, 0.0..=1.0.
+    pub l_squint: f32,
+    pub r_squint: f32,
+    // Whether eyelid sync moved both eyelids together.
+    pub eyelids_synced: bool,
+
     // Gaze for interaction.
     // Gaze direction without depth, can e.g. ignore one eye if it's closed, etc.
     pub gaze_pitch: f32,
     pub gaze_yaw: f32,
-    
+    // Origin of the gaze ray relative to the point between the eyes, meters.
+    pub gaze_origin: [f32; 3],
+    // Whether both eyes were forced to look in the same direction.
+    pub gaze_linked: bool,
+
+    // Distance the eyes converge at, meters, and the IPD it was computed with.
+    pub focus_distance: f32,
+    pub ipd: f32,
+
+    // Per eye confidence, 0.0..=1.0. An eye that only mirrors the other one has zero confidence.
+    pub l_confidence: f32,
+    pub r_confidence: f32,
+
+    // Pupil dilation normalized against the observed range of this user, 0.0..=1.0.
+    pub l_pupil: f32,
+    pub r_pupil: f32,
+
+    // Blinks per minute, over the last minute.
+    pub blink_rate: f32,
+
+    // Whether the values of each eye come from a good sample of that eye.
+    pub l_valid: bool,
+    pub r_valid: bool,
+    pub l_tracking: EyeTrackingState,
+    pub r_tracking: EyeTrackingState,
+
+    // Angular velocities in degrees per second, for extrapolation.
+    pub pitch_velocity: f32,
+    pub l_yaw_velocity: f32,
+    pub r_yaw_velocity: f32,
+
     pub timestamp: Timestamp,
 }
 
@@ -73,17 +152,82 @@ impl Default for CombinedEyeGazeState {
             pitch: 0.0,
             l_yaw: 0.0,
             r_yaw: 0.0,
+            l_pitch: 0.0,
+            r_pitch: 0.0,
             l_eyelid: EYELID_NEUTRAL_VALUE,
             r_eyelid: EYELID_NEUTRAL_VALUE,
 
+            l_closed: 0.0,
+            r_closed: 0.0,
+            l_wide: 0.0,
+            r_wide: 0.0,
+            l_squint: 0.0,
+            r_squint: 0.0,
+            eyelids_synced: false,
+
             gaze_pitch: 0.0,
             gaze_yaw: 0.0,
+            gaze_origin: [0.0, 0.0, 0.0],
+            gaze_linked: false,
+
+            focus_distance: DEFAULT_FOCUS_DISTANCE,
+            ipd: DEFAULT_IPD,
+
+            l_confidence: 0.0,
+            r_confidence: 0.0,
+
+            l_pupil: 0.5,
+            r_pupil: 0.5,
+
+            blink_rate: 0.0,
+
+            l_valid: false,
+            r_valid: false,
+            l_tracking: EyeTrackingState::Lost,
+            r_tracking: EyeTrackingState::Lost,
+
+            pitch_velocity: 0.0,
+            l_yaw_velocity: 0.0,
+            r_yaw_velocity: 0.0,
 
             timestamp: ZERO_TIMESTAMP,
         }
     }
 }
 
+impl CombinedEyeGazeState {
+    /// Extrapolates the angles to `time`, which may also be before the sample.
+    /// Confidence decays with the distance to the sample.
+    pub fn predict(&self, time: Timestamp) -> Self {
+        let (horizon, sign) = match time.duration_since(self.timestamp) {
+            Ok(ahead) => (ahead, 1.0),
+            Err(err) => (err.duration(), -1.0),
+        };
+        let horizon = horizon.min(MAX_PREDICTION);
+        let dt = horizon.as_secs_f32() * sign;
+        let confidence = prediction_confidence(horizon);
+
+        let yaw_velocity = (self.l_yaw_velocity + self.r_yaw_velocity) / 2.0;
+
+        Self {
+            pitch: self.pitch + self.pitch_velocity * dt,
+            l_yaw: self.l_yaw + self.l_yaw_velocity * dt,
+            r_yaw: self.r_yaw + self.r_yaw_velocity * dt,
+            l_pitch: self.l_pitch + self.pitch_velocity * dt,
+            r_pitch: self.r_pitch + self.pitch_velocity * dt,
+
+            gaze_pitch: self.gaze_pitch + self.pitch_velocity * dt,
+            gaze_yaw: self.gaze_yaw + yaw_velocity * dt,
+
+            l_confidence: self.l_confidence * confidence,
+            r_confidence: self.r_confidence * confidence,
+
+            timestamp: time,
+            ..*self
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, PartialEq)]
 pub enum EyesFrameType {
     Left,
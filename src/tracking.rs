@@ -0,0 +1,173 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::structs::{EyeGazeState, EyeTrackingState, Timestamp, ZERO_TIMESTAMP};
+
+/// What a degraded or lost eye outputs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EyeFallback {
+    /// Copy the other eye, or ease to neutral if that one isn't tracked either.
+    Mirror,
+    /// Keep the last good values.
+    Hold,
+    /// Move from the last good values to looking straight with a relaxed eyelid.
+    EaseToNeutral,
+}
+
+impl EyeFallback {
+    pub const ALL: [EyeFallback; 3] = [
+        EyeFallback::Mirror,
+        EyeFallback::Hold,
+        EyeFallback::EaseToNeutral,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EyeFallback::Mirror => "Mirror",
+            EyeFallback::Hold => "Hold",
+            EyeFallback::EaseToNeutral => "Ease to neutral",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TrackingSettings {
+    /// An eye without a good sample for this long is degraded.
+    pub degraded_after: Duration,
+    /// And lost after this long.
+    pub lost_after: Duration,
+    pub degraded_fallback: EyeFallback,
+    pub lost_fallback: EyeFallback,
+    /// Time `EyeFallback::EaseToNeutral` takes to reach neutral, seconds.
+    pub ease_time: f32,
+}
+
+impl Default for TrackingSettings {
+    fn default() -> Self {
+        Self {
+            degraded_after: Duration::from_millis(50),
+            lost_after: Duration::from_secs(1),
+            degraded_fallback: EyeFallback::Mirror,
+            lost_fallback: EyeFallback::Mirror,
+            ease_time: 0.5,
+        }
+    }
+}
+
+impl TrackingSettings {
+    /// Fallback of an eye that isn't tracking.
+    pub fn fallback(&self, state: EyeTrackingState) -> EyeFallback {
+        match state {
+            EyeTrackingState::Lost => self.lost_fallback,
+            _ => self.degraded_fallback,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct TrackingControl {
+    settings: Mutex<TrackingSettings>,
+}
+
+impl TrackingControl {
+    pub fn settings(&self) -> TrackingSettings {
+        *self.settings.lock().unwrap()
+    }
+
+    pub fn set_settings(&self, settings: TrackingSettings) {
+        *self.settings.lock().unwrap() = settings;
+    }
+}
+
+/// Tracking state of one eye, from the age of its last good sample.
+#[derive(Debug)]
+pub struct EyeTracker {
+    last_good: Option<(EyeGazeState, Timestamp)>,
+    last_sample_valid: bool,
+    state: EyeTrackingState,
+    // When the eye stopped tracking, degraded and lost alike, so the ease doesn't start
+    // over when a degraded eye is lost.
+    untracked_since: Timestamp,
+}
+
+impl Default for EyeTracker {
+    fn default() -> Self {
+        Self {
+            last_good: None,
+            last_sample_valid: false,
+            state: EyeTrackingState::Lost,
+            untracked_since: ZERO_TIMESTAMP,
+        }
+    }
+}
+
+impl EyeTracker {
+    pub fn update(&mut self, state: &EyeGazeState, timestamp: Timestamp) {
+        self.last_sample_valid = state.valid;
+        if state.valid {
+            self.last_good = Some((*state, timestamp));
+        }
+    }
+
+    /// A rejected sample degrades the eye right away, missing samples after a timeout.
+    pub fn evaluate(&mut self, now: Timestamp, settings: &TrackingSettings) -> EyeTrackingState {
+        let state = match self.last_good {
+            None => EyeTrackingState::Lost,
+            Some((_, timestamp)) => {
+                let age = now.duration_since(timestamp).unwrap_or_default();
+                if age > settings.lost_after {
+                    EyeTrackingState::Lost
+                } else if age > settings.degraded_after || !self.last_sample_valid {
+                    EyeTrackingState::Degraded
+                } else {
+                    EyeTrackingState::Tracking
+                }
+            }
+        };
+
+        if self.state == EyeTrackingState::Tracking && state != EyeTrackingState::Tracking {
+            self.untracked_since = now;
+        }
+        self.state = state;
+        state
+    }
+
+    /// Values of the eye when it doesn't mirror the other one.
+    pub fn fallback_state(
+        &self,
+        fallback: EyeFallback,
+        now: Timestamp,
+        settings: &TrackingSettings,
+        neutral_eyelid: f32,
+    ) -> EyeGazeState {
+        let neutral = EyeGazeState {
+            eyelid: neutral_eyelid,
+            ..Default::default()
+        };
+        let Some((held, _)) = self.last_good else {
+            return neutral;
+        };
+
+        match fallback {
+            EyeFallback::Hold => EyeGazeState {
+                confidence: 0.0,
+                valid: false,
+                ..held
+            },
+            EyeFallback::Mirror | EyeFallback::EaseToNeutral => {
+                let elapsed = now.duration_since(self.untracked_since).unwrap_or_default();
+                let t = (elapsed.as_secs_f32() / settings.ease_time.max(f32::EPSILON)).min(1.0);
+                let ease = |from: f32, to: f32| from + (to - from) * t;
+
+                EyeGazeState {
+                    pitch: ease(held.pitch, neutral.pitch),
+                    yaw: ease(held.yaw, neutral.yaw),
+                    eyelid: ease(held.eyelid, neutral.eyelid),
+                    confidence: 0.0,
+                    valid: false,
+                    ..held
+                }
+            }
+        }
+    }
+}
@@ -5,9 +5,33 @@ use crate::{camera::Frame, structs::EyeGazeState};
 
 #[cfg(feature = "inference")]
 use crate::inference::{
-    FRAME_CROP_H, FRAME_CROP_W, FRAME_CROP_X, FRAME_CROP_Y, FRAME_RESIZE_H, FRAME_RESIZE_W,
+    FRAME_RESIZE_H, FRAME_RESIZE_W, InferenceControl, InferenceSettings, ModelInputFrame,
+};
+#[cfg(feature = "inference")]
+use crate::blink::BlinkEvent;
+#[cfg(feature = "inference")]
+use crate::calibration::CalibrationControl;
+#[cfg(feature = "inference")]
+use crate::eye_movements::{MovementControl, MovementEvent};
+#[cfg(feature = "inference")]
+use crate::filters::FilterControl;
+#[cfg(feature = "inference")]
+use crate::osc_sender::{OscControl, OscDestination};
+#[cfg(feature = "inference")]
+use crate::output_profiles::{Output, OutputProfileControl};
+#[cfg(feature = "inference")]
+use crate::post_processing::{GazeLink, PostProcessControl};
+#[cfg(feature = "inference")]
+use crate::recenter::RecenterControl;
+#[cfg(feature = "inference")]
+use crate::tracking::{EyeFallback, TrackingControl};
+#[cfg(feature = "inference")]
+use crate::vergence::VergenceControl;
+#[cfg(feature = "inference")]
+use std::sync::Arc;
+use crate::structs::{
+    CombinedEyeGazeState, EYELID_NEUTRAL_VALUE, Eye, EyesFrame, EyesGazeState,
 };
-use crate::structs::{CombinedEyeGazeState, Eye, EyesFrame, EyesGazeState};
 use async_broadcast::Receiver;
 use image::{DynamicImage, ImageBuffer, Rgb, SubImage};
 use imgui::ImColor32;
@@ -15,21 +39,102 @@ use imgui::ImColor32;
 pub const UI_WINDOW_W: u32 = 1280;
 pub const UI_WINDOW_H: u32 = 720;
 
+/// Size of the OpenXR overlay quad and its distance from the head, in meters.
+pub const UI_OVERLAY_WIDTH: f32 = 1.6;
+pub const UI_OVERLAY_HEIGHT: f32 = 0.9;
+pub const UI_OVERLAY_DISTANCE: f32 = 1.5;
+
+/// Position in the UI of a direction relative to the head, in degrees.
+/// Exact on the OpenXR overlay, on desktop it assumes the window covers the same view.
+#[cfg(feature = "inference")]
+fn gaze_to_ui_position(pitch: f32, yaw: f32) -> [f32; 2] {
+    let x = yaw.to_radians().tan() * UI_OVERLAY_DISTANCE / UI_OVERLAY_WIDTH;
+    let y = pitch.to_radians().tan() * UI_OVERLAY_DISTANCE / UI_OVERLAY_HEIGHT;
+    [
+        (0.5 + x) * UI_WINDOW_W as f32,
+        (0.5 - y) * UI_WINDOW_H as f32,
+    ]
+}
+
 pub struct AppRendererContext {
     pub eyes_cam_rx: Receiver<EyesFrame>,
     pub f_rx: Receiver<Frame>,
 
     pub raw_eyes_rx: Receiver<EyesGazeState>,
     pub combined_eyes_rx: Receiver<CombinedEyeGazeState>,
+
+    #[cfg(feature = "inference")]
+    pub inference_control: Arc<InferenceControl>,
+    #[cfg(feature = "inference")]
+    pub model_input_rx: Receiver<ModelInputFrame>,
+    #[cfg(feature = "inference")]
+    pub filter_control: Arc<FilterControl>,
+    #[cfg(feature = "inference")]
+    pub calibration_control: Arc<CalibrationControl>,
+    #[cfg(feature = "inference")]
+    pub recenter_control: Arc<RecenterControl>,
+    #[cfg(feature = "inference")]
+    pub blink_rx: Receiver<BlinkEvent>,
+    #[cfg(feature = "inference")]
+    pub movement_control: Arc<MovementControl>,
+    #[cfg(feature = "inference")]
+    pub movement_rx: Receiver<MovementEvent>,
+    #[cfg(feature = "inference")]
+    pub vergence_control: Arc<VergenceControl>,
+    #[cfg(feature = "inference")]
+    pub tracking_control: Arc<TrackingControl>,
+    #[cfg(feature = "inference")]
+    pub post_process_control: Arc<PostProcessControl>,
+    #[cfg(feature = "inference")]
+    pub output_profile_control: Arc<OutputProfileControl>,
+    #[cfg(feature = "inference")]
+    pub osc_control: Arc<OscControl>,
 }
 pub(crate) struct AppRenderer {
     r_texture: CameraTexture,
     f_texture: CameraTexture,
     l_texture: CameraTexture,
 
+    // Exact model input published by the inference, upscaled to the camera texture size.
+    #[cfg(feature = "inference")]
+    l_input_texture: CameraTexture,
+    #[cfg(feature = "inference")]
+    r_input_texture: CameraTexture,
+
     l_raw_eye: EyeGazeState,
     r_raw_eye: EyeGazeState,
     filtered_eyes: CombinedEyeGazeState,
+
+    #[cfg(feature = "inference")]
+    inference_control: Option<Arc<InferenceControl>>,
+    #[cfg(feature = "inference")]
+    model_path_input: String,
+    #[cfg(feature = "inference")]
+    model_status: String,
+    #[cfg(feature = "inference")]
+    filter_control: Option<Arc<FilterControl>>,
+    #[cfg(feature = "inference")]
+    calibration_control: Option<Arc<CalibrationControl>>,
+    #[cfg(feature = "inference")]
+    recenter_control: Option<Arc<RecenterControl>>,
+    #[cfg(feature = "inference")]
+    last_blink: Option<BlinkEvent>,
+    #[cfg(feature = "inference")]
+    movement_control: Option<Arc<MovementControl>>,
+    #[cfg(feature = "inference")]
+    last_movement: Option<MovementEvent>,
+    #[cfg(feature = "inference")]
+    vergence_control: Option<Arc<VergenceControl>>,
+    #[cfg(feature = "inference")]
+    tracking_control: Option<Arc<TrackingControl>>,
+    #[cfg(feature = "inference")]
+    post_process_control: Option<Arc<PostProcessControl>>,
+    #[cfg(feature = "inference")]
+    output_profile_control: Option<Arc<OutputProfileControl>>,
+    #[cfg(feature = "inference")]
+    osc_control: Option<Arc<OscControl>>,
+    #[cfg(feature = "inference")]
+    osc_address_input: String,
 }
 
 impl AppRenderer {
@@ -39,9 +144,45 @@ impl AppRenderer {
             r_texture: CameraTexture::new(device, renderer, Some("R texture")),
             f_texture: CameraTexture::new(device, renderer, Some("F texture")),
 
+            #[cfg(feature = "inference")]
+            l_input_texture: CameraTexture::new(device, renderer, Some("L input texture")),
+            #[cfg(feature = "inference")]
+            r_input_texture: CameraTexture::new(device, renderer, Some("R input texture")),
+
             l_raw_eye: EyeGazeState::default(),
             r_raw_eye: EyeGazeState::default(),
             filtered_eyes: CombinedEyeGazeState::default(),
+
+            #[cfg(feature = "inference")]
+            inference_control: None,
+            #[cfg(feature = "inference")]
+            model_path_input: String::new(),
+            #[cfg(feature = "inference")]
+            model_status: String::new(),
+            #[cfg(feature = "inference")]
+            filter_control: None,
+            #[cfg(feature = "inference")]
+            calibration_control: None,
+            #[cfg(feature = "inference")]
+            recenter_control: None,
+            #[cfg(feature = "inference")]
+            last_blink: None,
+            #[cfg(feature = "inference")]
+            movement_control: None,
+            #[cfg(feature = "inference")]
+            last_movement: None,
+            #[cfg(feature = "inference")]
+            vergence_control: None,
+            #[cfg(feature = "inference")]
+            tracking_control: None,
+            #[cfg(feature = "inference")]
+            post_process_control: None,
+            #[cfg(feature = "inference")]
+            output_profile_control: None,
+            #[cfg(feature = "inference")]
+            osc_control: None,
+            #[cfg(feature = "inference")]
+            osc_address_input: String::new(),
         }
     }
 
@@ -83,6 +224,97 @@ impl AppRenderer {
             }
         }
 
+        // Only the latest blink event is shown.
+        #[cfg(feature = "inference")]
+        loop {
+            match renderer_context.blink_rx.try_recv() {
+                Ok(event) => self.last_blink = Some(event),
+                Err(err) => match err {
+                    async_broadcast::TryRecvError::Overflowed(_) => continue,
+                    async_broadcast::TryRecvError::Closed
+                    | async_broadcast::TryRecvError::Empty => break,
+                },
+            };
+        }
+
+        #[cfg(feature = "inference")]
+        loop {
+            match renderer_context.movement_rx.try_recv() {
+                Ok(event) => self.last_movement = Some(event),
+                Err(err) => match err {
+                    async_broadcast::TryRecvError::Overflowed(_) => continue,
+                    async_broadcast::TryRecvError::Closed
+                    | async_broadcast::TryRecvError::Empty => break,
+                },
+            };
+        }
+
+        #[cfg(feature = "inference")]
+        if let Some(input_frame) = loop {
+            match renderer_context.model_input_rx.try_recv() {
+                Ok(frame) => break Some(frame),
+                Err(err) => match err {
+                    async_broadcast::TryRecvError::Overflowed(_) => continue,
+                    async_broadcast::TryRecvError::Closed
+                    | async_broadcast::TryRecvError::Empty => break None,
+                },
+            };
+        } {
+            use crate::segmentation::SegmentationClass;
+
+            // Segmentation masks are tinted over the input, pupil red, iris green, sclera blue.
+            let prepare_input = |input: image::GrayImage, mask: Option<image::GrayImage>| {
+                let mut image = DynamicImage::ImageLuma8(input)
+                    .resize_exact(
+                        CAMERA_FRAME_SIZE,
+                        CAMERA_FRAME_SIZE,
+                        image::imageops::FilterType::Nearest,
+                    )
+                    .into_rgba8();
+
+                if let Some(mask) = mask {
+                    const MASK_ALPHA: f32 = 0.4;
+
+                    let (mask_w, mask_h) = mask.dimensions();
+                    for (x, y, pixel) in image.enumerate_pixels_mut() {
+                        let class = SegmentationClass::from_mask_value(
+                            mask.get_pixel(
+                                x * mask_w / CAMERA_FRAME_SIZE,
+                                y * mask_h / CAMERA_FRAME_SIZE,
+                            )[0],
+                        );
+                        let color = match class {
+                            SegmentationClass::Background => continue,
+                            SegmentationClass::Sclera => [0.0, 0.0, 255.0],
+                            SegmentationClass::Iris => [0.0, 255.0, 0.0],
+                            SegmentationClass::Pupil => [255.0, 0.0, 0.0],
+                        };
+                        for (channel, color) in pixel.0.iter_mut().zip(color) {
+                            *channel =
+                                (*channel as f32 * (1.0 - MASK_ALPHA) + color * MASK_ALPHA) as u8;
+                        }
+                    }
+                }
+
+                image
+            };
+
+            if let Some(input) = input_frame.l_input {
+                self.l_input_texture.upload_texture(
+                    &prepare_input(input, input_frame.l_mask),
+                    queue,
+                    renderer,
+                );
+            }
+            if let Some(input) = input_frame.r_input {
+                self.r_input_texture.upload_texture(
+                    &prepare_input(input, input_frame.r_mask),
+                    queue,
+                    renderer,
+                );
+            }
+        }
+
         self.f_texture
             .update_texture(&mut renderer_context.f_rx, queue, renderer);
 
@@ -123,10 +355,56 @@ impl AppRenderer {
             };
         }
         .unwrap_or(self.filtered_eyes);
+
+        #[cfg(feature = "inference")]
+        {
+            let control = &renderer_context.inference_control;
+            if self.inference_control.is_none() {
+                self.model_path_input = control.settings().model_path.display().to_string();
+                self.inference_control = Some(control.clone());
+            }
+            self.model_status = control.status();
+
+            if self.filter_control.is_none() {
+                self.filter_control = Some(renderer_context.filter_control.clone());
+            }
+            if self.calibration_control.is_none() {
+                self.calibration_control = Some(renderer_context.calibration_control.clone());
+            }
+            if self.recenter_control.is_none() {
+                self.recenter_control = Some(renderer_context.recenter_control.clone());
+            }
+            if self.movement_control.is_none() {
+                self.movement_control = Some(renderer_context.movement_control.clone());
+            }
+            if self.vergence_control.is_none() {
+                self.vergence_control = Some(renderer_context.vergence_control.clone());
+            }
+            if self.tracking_control.is_none() {
+                self.tracking_control = Some(renderer_context.tracking_control.clone());
+            }
+            if self.post_process_control.is_none() {
+                self.post_process_control = Some(renderer_context.post_process_control.clone());
+            }
+            if self.output_profile_control.is_none() {
+                self.output_profile_control = Some(renderer_context.output_profile_control.clone());
+            }
+            if self.osc_control.is_none() {
+                self.osc_control = Some(renderer_context.osc_control.clone());
+            }
+        }
+    }
+
+    /// Trigger and grip double pressed on a controller.
+    pub(crate) fn special_gesture_triggered(&self) {
+        #[cfg(feature = "inference")]
+        if let Some(control) = self.recenter_control.as_ref() {
+            control.request();
+        }
     }
 
     pub(crate) fn render(
-        &self,
+        &mut self,
         ui: &imgui::Ui,
         #[cfg(feature = "openxr-api-layer")] openxr_modules: &mut OpenXRModules,
     ) {
@@ -156,9 +434,45 @@ impl AppRenderer {
         #[cfg(feature = "inference")]
         self.draw_inference_window(ui);
 
+        #[cfg(feature = "inference")]
+        self.draw_model_window(ui);
+
+        #[cfg(feature = "inference")]
+        self.draw_preprocessing_window(ui);
+
+        #[cfg(feature = "inference")]
+        self.draw_filters_window(ui);
+
+        #[cfg(feature = "inference")]
+        self.draw_calibration_window(ui);
+
+        #[cfg(feature = "inference")]
+        self.draw_recenter_window(ui);
+
+        #[cfg(feature = "inference")]
+        self.draw_movements_window(ui);
+
+        #[cfg(feature = "inference")]
+        self.draw_vergence_window(ui);
+
+        #[cfg(feature = "inference")]
+        self.draw_tracking_window(ui);
+
+        #[cfg(feature = "inference")]
+        self.draw_post_processing_window(ui);
+
+        #[cfg(feature = "inference")]
+        self.draw_output_profiles_window(ui);
+
+        #[cfg(feature = "inference")]
+        self.draw_osc_window(ui);
+
         #[cfg(feature = "openxr-api-layer")]
         self.draw_openxr_modules(ui, openxr_modules);
 
+        #[cfg(feature = "inference")]
+        self.draw_calibration_target(ui);
+
         // Draw cursor.
         {
             const COLOR_INNER: ImColor32 = ImColor32::BLACK;
@@ -211,7 +525,6 @@ impl AppRenderer {
 
     #[cfg(feature = "inference")]
     fn draw_inference_window(&self, ui: &imgui::Ui) {
-        use crate::camera::CAMERA_FRAME_SIZE;
         use imgui::ImColor32;
 
         ui.window("Inference")
@@ -221,41 +534,39 @@ impl AppRenderer {
                 imgui::Condition::FirstUseEver,
             )
             .build(move || {
-                // Cropped Camera Feeds
+                // Model Input
 
-                let draw_cropped_feed = |camera_texture: CameraTexture| {
+                let draw_model_input = |camera_texture: CameraTexture| {
                     imgui::Image::new(
                         camera_texture.get_texture_id(),
-                        [FRAME_RESIZE_W as f32, FRAME_RESIZE_H as f32],
+                        [FRAME_RESIZE_W as f32 * 2.0, FRAME_RESIZE_H as f32 * 2.0],
                     )
-                    .uv0([
-                        1.0 - FRAME_CROP_X as f32 / CAMERA_FRAME_SIZE as f32,
-                        FRAME_CROP_Y as f32 / CAMERA_FRAME_SIZE as f32,
-                    ])
-                    .uv1([
-                        1.0 - (FRAME_CROP_X + FRAME_CROP_W) as f32 / CAMERA_FRAME_SIZE as f32,
-                        (FRAME_CROP_Y + FRAME_CROP_H) as f32 / CAMERA_FRAME_SIZE as f32,
-                    ])
                     .build(ui);
                 };
 
-                ui.text("Cropped Camera Feeds");
+                // The right eye is flipped, just like the model sees it.
+                ui.text("Model Input");
                 let group = ui.begin_group();
-                draw_cropped_feed(self.l_texture);
+                draw_model_input(self.l_input_texture);
                 ui.same_line();
-                draw_cropped_feed(self.r_texture);
+                draw_model_input(self.r_input_texture);
                 group.end();
 
                 // Generic eye state drawer
 
-                let draw_eyelid_state = |eyelid: f32| {
+                // Split at the calibrated neutral of the eye.
+                let draw_eyelid_state = |eye: Eye, eyelid: f32| {
                     const WIDGET_W: f32 = 10.0;
                     const WIDGET_H: f32 = 150.0;
 
                     const COLOR_NORMAL: ImColor32 = ImColor32::from_rgb(0, 148, 255);
                     const COLOR_WIDE: ImColor32 = ImColor32::from_rgb(127, 201, 255);
 
-                    const SPLIT_POINT: f32 = 0.75;
+                    let split_point = self
+                        .calibration_control
+                        .as_ref()
+                        .map(|control| control.eyelid(eye).neutral)
+                        .unwrap_or(EYELID_NEUTRAL_VALUE);
 
                     let progress = eyelid;
 
@@ -263,7 +574,7 @@ impl AppRenderer {
                     let position = ui.cursor_screen_pos();
 
                     let zero_y = position[1] + WIDGET_H;
-                    let split_y = position[1] + WIDGET_H * (1.0 - progress.min(SPLIT_POINT));
+                    let split_y = position[1] + WIDGET_H * (1.0 - progress.min(split_point));
                     let one_y = position[1] + WIDGET_H * (1.0 - progress);
 
                     draw_list
@@ -387,36 +698,803 @@ impl AppRenderer {
 
                 ui.text("Raw Eye State");
                 let group = ui.begin_group();
-                draw_eyelid_state(self.l_raw_eye.eyelid);
+                draw_eyelid_state(Eye::L, self.l_raw_eye.eyelid);
                 ui.same_line();
                 draw_gaze_state((self.l_raw_eye.pitch, self.l_raw_eye.yaw), None);
                 ui.same_line();
                 draw_gaze_state((self.r_raw_eye.pitch, self.r_raw_eye.yaw), None);
                 ui.same_line();
-                draw_eyelid_state(self.r_raw_eye.eyelid);
+                draw_eyelid_state(Eye::R, self.r_raw_eye.eyelid);
                 group.end();
+                ui.text(format!(
+                    "Confidence: L {:.2}, R {:.2}",
+                    self.l_raw_eye.confidence, self.r_raw_eye.confidence
+                ));
+                ui.text(format!(
+                    "Valid: L {}, R {}",
+                    self.l_raw_eye.valid, self.r_raw_eye.valid
+                ));
 
                 // Filtered Eye State
 
                 ui.text("Filtered Eye State");
                 let group = ui.begin_group();
-                draw_eyelid_state(self.filtered_eyes.l_eyelid);
+                draw_eyelid_state(Eye::L, self.filtered_eyes.l_eyelid);
                 ui.same_line();
                 draw_gaze_state(
                     (self.l_raw_eye.pitch, self.l_raw_eye.yaw),
-                    Some((self.filtered_eyes.pitch, self.filtered_eyes.l_yaw)),
+                    Some((self.filtered_eyes.l_pitch, self.filtered_eyes.l_yaw)),
                 );
                 ui.same_line();
                 draw_gaze_state(
                     (self.r_raw_eye.pitch, self.r_raw_eye.yaw),
-                    Some((self.filtered_eyes.pitch, self.filtered_eyes.r_yaw)),
+                    Some((self.filtered_eyes.r_pitch, self.filtered_eyes.r_yaw)),
                 );
                 ui.same_line();
-                draw_eyelid_state(self.filtered_eyes.r_eyelid);
+                draw_eyelid_state(Eye::R, self.filtered_eyes.r_eyelid);
                 group.end();
+                ui.text(format!(
+                    "Pupil dilation: L {:.2}, R {:.2}",
+                    self.filtered_eyes.l_pupil, self.filtered_eyes.r_pupil
+                ));
+                ui.text(format!(
+                    "Tracking: L {:?}, R {:?}",
+                    self.filtered_eyes.l_tracking, self.filtered_eyes.r_tracking
+                ));
+                ui.text(format!(
+                    "Blink rate: {:.0}/min",
+                    self.filtered_eyes.blink_rate
+                ));
+                ui.text(format!(
+                    "Focus distance: {:.2} m",
+                    self.filtered_eyes.focus_distance
+                ));
+                match self.last_blink {
+                    Some(BlinkEvent::Start { eye, .. }) => ui.text(format!("Blinking: {eye:?}")),
+                    Some(BlinkEvent::End { eye, duration, .. }) => ui.text(format!(
+                        "Last blink: {eye:?} {} ms",
+                        duration.as_millis()
+                    )),
+                    None => ui.text("Last blink: none"),
+                }
+            });
+    }
+
+    #[cfg(feature = "inference")]
+    fn draw_model_window(&mut self, ui: &imgui::Ui) {
+        let Some(control) = self.inference_control.clone() else {
+            return;
+        };
+
+        ui.window("Model")
+            .position_pivot([1.0f32, 0.0f32])
+            .position([UI_WINDOW_W as f32, 0.0], imgui::Condition::FirstUseEver)
+            .build(|| {
+                ui.input_text("Path", &mut self.model_path_input).build();
+
+                let mut threads = control.settings().threads_per_eye as i32;
+                if ui.input_int("Threads per eye", &mut threads).build() && threads > 0 {
+                    control.update_settings(|settings| settings.threads_per_eye = threads as usize);
+                }
+
+                if let Some(_node) = ui.tree_node("Session options") {
+                    use crate::inference::ExecutionProviderKind;
+                    use clap::ValueEnum;
+
+                    let mut settings = control.settings();
+                    let mut changed = false;
+
+                    let selected = format!("{:?}", settings.execution_provider);
+                    if let Some(_combo) = ui.begin_combo("Execution provider", selected) {
+                        for &provider in ExecutionProviderKind::value_variants() {
+                            if ui.selectable(format!("{provider:?}")) {
+                                settings.execution_provider = provider;
+                                changed = true;
+                            }
+                        }
+                    }
+
+                    let mut inter_threads = settings.inter_threads as i32;
+                    if ui.input_int("Inter-op threads", &mut inter_threads).build()
+                        && inter_threads > 0
+                    {
+                        settings.inter_threads = inter_threads as usize;
+                        changed = true;
+                    }
+
+                    changed |= ui.checkbox("Parallel execution", &mut settings.parallel_execution);
+                    changed |= ui.checkbox("Memory arena", &mut settings.memory_arena);
+                    changed |= ui.checkbox("Intra-op spinning", &mut settings.intra_op_spinning);
+                    changed |= ui.checkbox("Inter-op spinning", &mut settings.inter_op_spinning);
+
+                    if changed {
+                        control.update_settings(|s| *s = InferenceSettings {
+                            model_path: s.model_path.clone(),
+                            threads_per_eye: s.threads_per_eye,
+                            ..settings
+                        });
+                    }
+                }
+
+                if let Some(_node) = ui.tree_node("Segmentation calibration") {
+                    for (eye, label) in [(Eye::L, "Left Eye"), (Eye::R, "Right Eye")] {
+                        let _id = ui.push_id(label);
+                        ui.text(label);
+
+                        let features = control.features(eye);
+                        let mut mapping = control.segmentation_mapping(eye);
+                        let mut changed = false;
+
+                        match features {
+                            Some(features) => ui.text(format!(
+                                "Pupil: {:?}, aperture: {:.3}",
+                                features.pupil_center, features.aperture
+                            )),
+                            None => ui.text("No segmentation output"),
+                        }
+
+                        let pupil_center = features.and_then(|f| f.pupil_center);
+                        if ui.button("Looking straight") {
+                            if let Some(pupil_center) = pupil_center {
+                                mapping.center = pupil_center;
+                                changed = true;
+                            }
+                        }
+                        ui.same_line();
+                        if ui.button("Eye closed") {
+                            if let Some(features) = features {
+                                mapping.aperture_closed = features.aperture;
+                                changed = true;
+                            }
+                        }
+                        ui.same_line();
+                        if ui.button("Eye wide open") {
+                            if let Some(features) = features {
+                                mapping.aperture_open = features.aperture;
+                                changed = true;
+                            }
+                        }
+
+                        changed |= ui.slider("Yaw gain", -90.0, 90.0, &mut mapping.gain[0]);
+                        changed |= ui.slider("Pitch gain", -90.0, 90.0, &mut mapping.gain[1]);
+                        changed |=
+                            ui.slider("Closed aperture", 0.0, 1.0, &mut mapping.aperture_closed);
+                        changed |= ui.slider("Open aperture", 0.0, 1.0, &mut mapping.aperture_open);
+
+                        if changed {
+                            control.set_segmentation_mapping(eye, mapping);
+                        }
+                    }
+                }
+
+                if let Some(_node) = ui.tree_node("Output validation") {
+                    let mut limits = control.output_limits();
+                    let mut changed = false;
+
+                    changed |= ui.slider("Max pitch", 0.0, 90.0, &mut limits.max_pitch);
+                    changed |= ui.slider("Max yaw", 0.0, 90.0, &mut limits.max_yaw);
+                    changed |= ui.slider("Min eyelid", -1.0, 1.0, &mut limits.min_eyelid);
+                    changed |= ui.slider("Max eyelid", 0.0, 2.0, &mut limits.max_eyelid);
+                    changed |= ui.slider(
+                        "Max speed (deg/s)",
+                        100.0,
+                        5000.0,
+                        &mut limits.max_angular_speed,
+                    );
+
+                    if changed {
+                        control.set_output_limits(limits);
+                    }
+
+                    ui.text_wrapped(control.faults().summary());
+                    if ui.button("Reset counters") {
+                        control.faults().reset();
+                    }
+                }
+
+                if ui.button("Load") {
+                    let model_path = self.model_path_input.clone();
+                    control.update_settings(|settings| settings.model_path = model_path.into());
+                }
+                ui.same_line();
+                if ui.button("Reload") {
+                    control.request_reload();
+                }
+
+                ui.text_wrapped(&self.model_status);
+            });
+    }
+
+    #[cfg(feature = "inference")]
+    fn draw_preprocessing_window(&self, ui: &imgui::Ui) {
+        use crate::preprocessing::{Equalization, PreprocessingSettings};
+
+        let Some(control) = self.inference_control.as_ref() else {
+            return;
+        };
+
+        let edit_settings = |settings: &mut PreprocessingSettings| {
+            let mut changed = false;
+
+            changed |= ui.checkbox("Vignette correction", &mut settings.vignette_correction);
+            if settings.vignette_correction {
+                changed |= ui.slider("Strength", 0.0, 2.0, &mut settings.vignette_strength);
+            }
+
+            changed |= ui.checkbox("Denoise", &mut settings.denoise);
+            if settings.denoise {
+                changed |= ui.slider("Sigma", 0.1, 3.0, &mut settings.denoise_sigma);
+            }
+
+            changed |= ui.checkbox("Auto gain", &mut settings.auto_gain);
+
+            let equalization_str = format!("{:?}", settings.equalization);
+            if let Some(_combo) = ui.begin_combo("Equalization", equalization_str) {
+                for equalization in [Equalization::None, Equalization::Histogram, Equalization::Clahe]
+                {
+                    if ui.selectable(format!("{equalization:?}")) {
+                        settings.equalization = equalization;
+                        changed = true;
+                    }
+                }
+            }
+            if settings.equalization == Equalization::Clahe {
+                changed |= ui.slider("Clip limit", 1.0, 8.0, &mut settings.clahe_clip_limit);
+                changed |= ui.slider("Tiles", 1, 8, &mut settings.clahe_tiles);
+            }
+
+            changed |= ui.checkbox("Gamma correction", &mut settings.gamma_correction);
+            if settings.gamma_correction {
+                changed |= ui.slider("Gamma", 0.2, 3.0, &mut settings.gamma);
+            }
+
+            changed
+        };
+
+        ui.window("Preprocessing")
+            .position_pivot([1.0f32, 1.0f32])
+            .position(
+                [UI_WINDOW_W as f32, UI_WINDOW_H as f32],
+                imgui::Condition::FirstUseEver,
+            )
+            .build(|| {
+                for (eye, label) in [(Eye::L, "Left Eye"), (Eye::R, "Right Eye")] {
+                    let _id = ui.push_id(label);
+                    if let Some(_node) = ui.tree_node(label) {
+                        let mut settings = control.preprocessing(eye);
+                        if edit_settings(&mut settings) {
+                            control.set_preprocessing(eye, settings);
+                        }
+                    }
+                }
+            });
+    }
+
+    #[cfg(feature = "inference")]
+    fn draw_filters_window(&self, ui: &imgui::Ui) {
+        use crate::filters::{FilterKind, MAX_WINDOW};
+
+        let Some(control) = self.filter_control.as_ref() else {
+            return;
+        };
+
+        // Returns true if the chain has changed.
+        let edit_chain = |chain: &mut Vec<FilterKind>| {
+            let mut changed = false;
+            let mut remove = None;
+
+            for (i, filter) in chain.iter_mut().enumerate() {
+                let _id = ui.push_id_usize(i);
+                ui.text(filter.name());
+                ui.same_line();
+                if ui.small_button("Remove") {
+                    remove = Some(i);
+                }
+
+                match filter {
+                    FilterKind::OneEuro {
+                        min_cutoff,
+                        d_cutoff,
+                        beta,
+                    } => {
+                        changed |= ui.slider("Min cutoff (Hz)", 0.01, 10.0, min_cutoff);
+                        changed |= ui.slider("Derivative cutoff (Hz)", 0.01, 10.0, d_cutoff);
+                        changed |= ui.slider("Beta", 0.0, 5.0, beta);
+                    }
+                    FilterKind::Median { window } => {
+                        changed |= ui.slider("Window", 1, MAX_WINDOW, window);
+                    }
+                    FilterKind::Hampel { window, threshold } => {
+                        changed |= ui.slider("Window", 1, MAX_WINDOW, window);
+                        changed |= ui.slider("Threshold (MADs)", 0.5, 10.0, threshold);
+                    }
+                    FilterKind::Ema { time_constant } => {
+                        changed |= ui.slider("Time constant (s)", 0.0, 1.0, time_constant);
+                    }
+                    FilterKind::Deadzone { width } => {
+                        changed |= ui.slider("Width", 0.0, 5.0, width);
+                    }
+                }
+            }
+
+            if let Some(i) = remove {
+                chain.remove(i);
+                changed = true;
+            }
+
+            if let Some(_combo) = ui.begin_combo("Add filter", "...") {
+                for filter in FilterKind::ALL {
+                    if ui.selectable(filter.name()) {
+                        chain.push(filter);
+                        changed = true;
+                    }
+                }
+            }
+
+            changed
+        };
+
+        ui.window("Filters")
+            .position_pivot([0.0f32, 1.0f32])
+            .position([0.0, UI_WINDOW_H as f32], imgui::Condition::FirstUseEver)
+            .build(|| {
+                let mut settings = control.settings();
+                let mut changed = false;
+
+                for (label, chain) in [
+                    ("Pitch", &mut settings.pitch),
+                    ("Yaw", &mut settings.yaw),
+                    ("Eyelid", &mut settings.eyelid),
+                ] {
+                    let _id = ui.push_id(label);
+                    if let Some(_node) = ui.tree_node(label) {
+                        changed |= edit_chain(chain);
+                    }
+                }
+
+                changed |= ui.checkbox(
+                    "Restart gaze filters on saccades",
+                    &mut settings.restart_on_saccades,
+                );
+                if settings.restart_on_saccades {
+                    ui.text("Saccade velocity is set in the Eye Movements window.");
+                }
+
+                if changed {
+                    control.update_settings(|s| *s = settings);
+                }
             });
     }
 
+    #[cfg(feature = "inference")]
+    fn draw_vergence_window(&self, ui: &imgui::Ui) {
+        let Some(control) = self.vergence_control.as_ref() else {
+            return;
+        };
+
+        ui.window("Vergence").build(|| {
+            let mut settings = control.settings();
+            let mut ipd_mm = settings.ipd * 1000.0;
+
+            let mut changed = false;
+            if ui.slider("IPD (mm)", 50.0, 80.0, &mut ipd_mm) {
+                settings.ipd = ipd_mm / 1000.0;
+                changed = true;
+            }
+            changed |= ui.slider("Near limit (m)", 0.05, 1.0, &mut settings.min_distance);
+            changed |= ui.slider("Far limit (m)", 1.0, 50.0, &mut settings.max_distance);
+            changed |= ui.slider("Smoothing (s)", 0.0, 1.0, &mut settings.smoothing);
+
+            changed |= ui.checkbox("Independent eye pitch", &mut settings.independent_pitch);
+            if settings.independent_pitch {
+                changed |= ui.slider(
+                    "Max vertical disparity (deg)",
+                    0.0,
+                    20.0,
+                    &mut settings.max_pitch_disparity,
+                );
+            }
+
+            let origin_str = match settings.dominant_eye {
+                None => "Between the eyes",
+                Some(Eye::L) => "Left eye",
+                Some(Eye::R) => "Right eye",
+            };
+            if let Some(_combo) = ui.begin_combo("Gaze origin", origin_str) {
+                for (dominant_eye, name) in [
+                    (None, "Between the eyes"),
+                    (Some(Eye::L), "Left eye"),
+                    (Some(Eye::R), "Right eye"),
+                ] {
+                    if ui.selectable(name) {
+                        settings.dominant_eye = dominant_eye;
+                        changed = true;
+                    }
+                }
+            }
+
+            if changed {
+                control.set_settings(settings);
+            }
+        });
+    }
+
+    #[cfg(feature = "inference")]
+    fn draw_tracking_window(&self, ui: &imgui::Ui) {
+        use std::time::Duration;
+
+        let Some(control) = self.tracking_control.as_ref() else {
+            return;
+        };
+
+        ui.window("Tracking").build(|| {
+            let mut settings = control.settings();
+            let mut degraded_ms = settings.degraded_after.as_millis() as u32;
+            let mut lost_ms = settings.lost_after.as_millis() as u32;
+
+            let mut changed = false;
+            if ui.slider("Degraded after (ms)", 10, 500, &mut degraded_ms) {
+                settings.degraded_after = Duration::from_millis(degraded_ms as u64);
+                changed = true;
+            }
+            if ui.slider("Lost after (ms)", 100, 5000, &mut lost_ms) {
+                settings.lost_after = Duration::from_millis(lost_ms as u64);
+                changed = true;
+            }
+
+            let mut fallback_combo = |label: &str, fallback: &mut EyeFallback| {
+                if let Some(_combo) = ui.begin_combo(label, fallback.name()) {
+                    for option in EyeFallback::ALL {
+                        if ui.selectable(option.name()) {
+                            *fallback = option;
+                            changed = true;
+                        }
+                    }
+                }
+            };
+            fallback_combo("Degraded fallback", &mut settings.degraded_fallback);
+            fallback_combo("Lost fallback", &mut settings.lost_fallback);
+
+            changed |= ui.slider("Ease time (s)", 0.0, 3.0, &mut settings.ease_time);
+
+            if changed {
+                control.set_settings(settings);
+            }
+        });
+    }
+
+    #[cfg(feature = "inference")]
+    fn draw_post_processing_window(&self, ui: &imgui::Ui) {
+        let Some(control) = self.post_process_control.as_ref() else {
+            return;
+        };
+
+        ui.window("Post-processing").build(|| {
+            let mut settings = control.settings();
+
+            let mut changed = false;
+            changed |= ui.checkbox("Eyelid sync", &mut settings.eyelid_sync);
+            if settings.eyelid_sync {
+                changed |= ui.slider(
+                    "Sync threshold",
+                    0.0,
+                    0.5,
+                    &mut settings.eyelid_sync_threshold,
+                );
+            }
+
+            if let Some(_combo) = ui.begin_combo("Gaze linking", settings.gaze_link.name()) {
+                for gaze_link in GazeLink::ALL {
+                    if ui.selectable(gaze_link.name()) {
+                        settings.gaze_link = gaze_link;
+                        changed = true;
+                    }
+                }
+            }
+            if settings.gaze_link == GazeLink::WhenUnreliable {
+                changed |= ui.slider("Min confidence", 0.0, 1.0, &mut settings.link_confidence);
+                changed |= ui.slider(
+                    "Max divergence (deg)",
+                    0.0,
+                    10.0,
+                    &mut settings.max_divergence,
+                );
+            }
+
+            changed |= ui.checkbox("Derive squint and widen", &mut settings.squint_widen);
+            if settings.squint_widen {
+                changed |= ui.slider("Neutral range", 0.0, 0.5, &mut settings.neutral_range);
+                changed |= ui.slider("Full squint", 0.1, 1.0, &mut settings.full_squint);
+            }
+
+            if changed {
+                control.set_settings(settings);
+            }
+
+            ui.text(format!(
+                "Squint: L {:.2}, R {:.2}",
+                self.filtered_eyes.l_squint, self.filtered_eyes.r_squint
+            ));
+            ui.text(format!(
+                "Eyelids synced: {}, gaze linked: {}",
+                self.filtered_eyes.eyelids_synced, self.filtered_eyes.gaze_linked
+            ));
+        });
+    }
+
+    #[cfg(feature = "inference")]
+    fn draw_output_profiles_window(&self, ui: &imgui::Ui) {
+        let Some(control) = self.output_profile_control.as_ref() else {
+            return;
+        };
+
+        ui.window("Output Profiles").build(|| {
+            for output in Output::ALL {
+                let _id = ui.push_id(output.name());
+                let Some(_node) = ui.tree_node(output.name()) else {
+                    continue;
+                };
+
+                let mut profile = control.profile(output);
+
+                let mut changed = false;
+                changed |= ui.slider("Min vergence (deg)", 0.0, 1.0, &mut profile.min_vergence);
+                changed |= ui.slider("Pitch scale", 0.0, 2.0, &mut profile.pitch_scale);
+                changed |= ui.slider("Yaw scale", 0.0, 2.0, &mut profile.yaw_scale);
+                changed |= ui.checkbox("Invert pitch", &mut profile.invert_pitch);
+                changed |= ui.checkbox("Invert yaw", &mut profile.invert_yaw);
+                changed |= ui.slider("Max pitch (deg)", 0.0, 90.0, &mut profile.max_pitch);
+                changed |= ui.slider("Max yaw (deg)", 0.0, 90.0, &mut profile.max_yaw);
+
+                if changed {
+                    control.set_profile(output, profile);
+                }
+            }
+        });
+    }
+
+    #[cfg(feature = "inference")]
+    fn draw_osc_window(&mut self, ui: &imgui::Ui) {
+        let Some(control) = self.osc_control.clone() else {
+            return;
+        };
+
+        ui.window("OSC Output").build(|| {
+            let mut destinations = control.destinations();
+
+            let mut changed = false;
+            let mut removed = None;
+            for (index, destination) in destinations.iter_mut().enumerate() {
+                let _id = ui.push_id_usize(index);
+                let Some(_node) = ui.tree_node(&destination.address) else {
+                    continue;
+                };
+
+                let protocols = &mut destination.protocols;
+                changed |= ui.checkbox("VRChat native", &mut protocols.vrchat_native);
+                changed |= ui.checkbox("VRChat eyes closed", &mut protocols.eyes_closed);
+                changed |= ui.checkbox("VRCFT v2", &mut protocols.vrcft_v2);
+                changed |= ui.checkbox("Confidence", &mut protocols.confidence);
+                changed |= ui.checkbox("Focus distance", &mut protocols.focus_distance);
+
+                if ui.button("Remove") {
+                    removed = Some(index);
+                }
+            }
+
+            if let Some(index) = removed {
+                destinations.remove(index);
+                changed = true;
+            }
+
+            ui.input_text("Address", &mut self.osc_address_input)
+                .hint("host:port")
+                .build();
+            ui.same_line();
+            if ui.button("Add") && !self.osc_address_input.is_empty() {
+                let address = std::mem::take(&mut self.osc_address_input);
+                destinations.push(OscDestination::new(address));
+                changed = true;
+            }
+
+            if changed {
+                control.set_destinations(destinations);
+            }
+        });
+    }
+
+    #[cfg(feature = "inference")]
+    fn draw_movements_window(&self, ui: &imgui::Ui) {
+        use crate::eye_movements::MovementKind;
+        use std::time::Duration;
+
+        let Some(control) = self.movement_control.as_ref() else {
+            return;
+        };
+
+        ui.window("Eye Movements").build(|| {
+            let mut settings = control.settings();
+            let mut window_ms = settings.fixation_window.as_millis() as u32;
+
+            let mut changed = false;
+            changed |= ui.slider(
+                "Saccade velocity (deg/s)",
+                20.0,
+                500.0,
+                &mut settings.saccade_velocity,
+            );
+            changed |= ui.slider(
+                "Fixation dispersion (deg)",
+                0.1,
+                5.0,
+                &mut settings.fixation_dispersion,
+            );
+            if ui.slider("Fixation window (ms)", 20, 500, &mut window_ms) {
+                settings.fixation_window = Duration::from_millis(window_ms as u64);
+                changed = true;
+            }
+            if changed {
+                control.set_settings(settings);
+            }
+
+            match self.last_movement {
+                Some(event) => {
+                    let kind = match event.kind {
+                        MovementKind::Fixation => "Fixation",
+                        MovementKind::Saccade => "Saccade",
+                        MovementKind::SmoothPursuit => "Smooth pursuit",
+                    };
+                    ui.text(format!(
+                        "Last: {kind}, {} ms, amplitude {:.1} deg",
+                        event.duration.as_millis(),
+                        event.amplitude
+                    ));
+                    ui.text(format!(
+                        "Centroid: pitch {:.1}, yaw {:.1}",
+                        event.centroid.0, event.centroid.1
+                    ));
+                }
+                None => ui.text("Last: none"),
+            }
+        });
+    }
+
+    #[cfg(feature = "inference")]
+    fn draw_recenter_window(&self, ui: &imgui::Ui) {
+        let Some(control) = self.recenter_control.as_ref() else {
+            return;
+        };
+
+        ui.window("Recenter").build(|| {
+            ui.text("Look straight ahead while recentering.");
+            if ui.button("Recenter") {
+                control.request();
+            }
+            ui.same_line();
+            if ui.button("Reset") {
+                control.reset();
+            }
+
+            for eye in [Eye::L, Eye::R] {
+                let (pitch, yaw) = control.offset(eye);
+                ui.text(format!("{eye:?} zero: pitch {pitch:.1}, yaw {yaw:.1}"));
+            }
+
+            let mut settings = control.settings();
+
+            let mut changed = false;
+            changed |= ui.checkbox("Auto drift correction", &mut settings.auto_drift);
+            if settings.auto_drift {
+                changed |= ui.slider("Window (s)", 10.0, 600.0, &mut settings.drift_window);
+                changed |= ui.slider(
+                    "Max rate (deg/min)",
+                    0.1,
+                    10.0,
+                    &mut settings.max_drift_rate,
+                );
+            }
+
+            if changed {
+                control.set_settings(settings);
+            }
+        });
+    }
+
+    #[cfg(feature = "inference")]
+    fn draw_calibration_window(&self, ui: &imgui::Ui) {
+        let Some(control) = self.calibration_control.as_ref() else {
+            return;
+        };
+
+        ui.window("Calibration")
+            .position_pivot([0.5f32, 0.0f32])
+            .position([UI_WINDOW_W as f32 / 2.0, 0.0], imgui::Condition::FirstUseEver)
+            .build(|| {
+                if control.is_running() {
+                    if ui.button("Cancel") {
+                        control.cancel();
+                    }
+                } else {
+                    if ui.button("Start") {
+                        control.start();
+                    }
+                    ui.same_line();
+                    if ui.button("Clear") {
+                        control.clear();
+                    }
+                }
+
+                ui.text(control.status());
+
+                if let Some(_node) = ui.tree_node("Eyelids") {
+                    use crate::calibration::EyelidPose;
+
+                    // Calibration maps the model output, so it records the unfiltered eyelid.
+                    ui.text("Hold each pose and press its button.");
+                    for (eye, label, eyelid) in [
+                        (Eye::L, "Left Eye", self.l_raw_eye.eyelid),
+                        (Eye::R, "Right Eye", self.r_raw_eye.eyelid),
+                    ] {
+                        let _id = ui.push_id(label);
+                        let calibration = control.eyelid(eye);
+                        ui.text(format!(
+                            "{label}: closed {:.2}, neutral {:.2}, wide {:.2}",
+                            calibration.closed, calibration.neutral, calibration.wide
+                        ));
+                        for (pose, name) in [
+                            (EyelidPose::Closed, "Closed"),
+                            (EyelidPose::Neutral, "Neutral"),
+                            (EyelidPose::Wide, "Wide open"),
+                        ] {
+                            if ui.small_button(name) {
+                                control.record_eyelid(eye, pose, eyelid);
+                            }
+                            ui.same_line();
+                        }
+                        ui.new_line();
+                    }
+
+                    if ui.button("Reset eyelids") {
+                        control.reset_eyelids();
+                    }
+                }
+            });
+    }
+
+    /// Drawn over the windows, so the target stays visible wherever they are.
+    #[cfg(feature = "inference")]
+    fn draw_calibration_target(&self, ui: &imgui::Ui) {
+        const RADIUS: f32 = 24.0;
+
+        let Some(control) = self.calibration_control.as_ref() else {
+            return;
+        };
+        let Some((target, progress)) = control.current_target() else {
+            return;
+        };
+
+        let draw_list = ui.get_foreground_draw_list();
+        draw_list
+            .add_rect(
+                [0.0, 0.0],
+                [UI_WINDOW_W as f32, UI_WINDOW_H as f32],
+                ImColor32::from_rgba(0, 0, 0, 192),
+            )
+            .filled(true)
+            .build();
+
+        // Shrinks towards the point to look at.
+        let center = gaze_to_ui_position(target.pitch, target.yaw);
+        draw_list
+            .add_circle(center, RADIUS * (1.0 - progress) + 4.0, ImColor32::WHITE)
+            .thickness(2.0)
+            .build();
+        draw_list
+            .add_circle(center, 3.0, ImColor32::from_rgb(255, 0, 0))
+            .filled(true)
+            .build();
+    }
+
     #[cfg(feature = "openxr-api-layer")]
     fn draw_openxr_modules(&self, ui: &imgui::Ui, modules: &mut OpenXRModules) {
         ui.window("OpenXR: META Local Dimming").build(|| {
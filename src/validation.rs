@@ -0,0 +1,231 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::structs::{EyeGazeState, Timestamp};
+
+/// Plausible range of the model outputs, anything outside is rejected.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OutputLimits {
+    /// Degrees, in both directions.
+    pub max_pitch: f32,
+    /// Degrees, in both directions.
+    pub max_yaw: f32,
+    pub min_eyelid: f32,
+    pub max_eyelid: f32,
+    /// Degrees per second, saccades peak at roughly 700.
+    pub max_angular_speed: f32,
+}
+
+impl Default for OutputLimits {
+    fn default() -> Self {
+        Self {
+            max_pitch: 60.0,
+            max_yaw: 60.0,
+            min_eyelid: -0.1,
+            max_eyelid: 1.5,
+            max_angular_speed: 1000.0,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Fault {
+    NonFinite,
+    OutOfRange,
+    Jump,
+}
+
+/// Running totals of everything that went wrong, since start or the last reset.
+#[derive(Debug, Default)]
+pub struct FaultCounters {
+    pub non_finite: AtomicU64,
+    pub out_of_range: AtomicU64,
+    pub jumps: AtomicU64,
+    pub run_errors: AtomicU64,
+    pub recoveries: AtomicU64,
+}
+
+impl FaultCounters {
+    pub fn count(&self, fault: Fault) {
+        let counter = match fault {
+            Fault::NonFinite => &self.non_finite,
+            Fault::OutOfRange => &self.out_of_range,
+            Fault::Jump => &self.jumps,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn reset(&self) {
+        for counter in [
+            &self.non_finite,
+            &self.out_of_range,
+            &self.jumps,
+            &self.run_errors,
+            &self.recoveries,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    pub fn summary(&self) -> String {
+        let get = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        format!(
+            "NaN: {}, out of range: {}, jumps: {}, run errors: {}, recoveries: {}",
+            get(&self.non_finite),
+            get(&self.out_of_range),
+            get(&self.jumps),
+            get(&self.run_errors),
+            get(&self.recoveries),
+        )
+    }
+}
+
+/// Checks the outputs of one eye, rejected samples are replaced by the last good one.
+#[derive(Debug, Default)]
+pub struct OutputValidator {
+    last_valid: Option<(EyeGazeState, Timestamp)>,
+}
+
+impl OutputValidator {
+    /// Returns the state to publish and why the sample was rejected, if it was.
+    /// A rejected gaze is held, but the eyelid and pupil still follow the sample: blinks
+    /// often come with a wild gaze. The eyelid is range-checked on its own. Samples the
+    /// model itself marked invalid, e.g. no pupil in a closed eye, aren't faults.
+    pub fn validate(
+        &mut self,
+        state: EyeGazeState,
+        timestamp: Timestamp,
+        limits: &OutputLimits,
+    ) -> (EyeGazeState, Option<Fault>) {
+        let values = [
+            state.pitch,
+            state.yaw,
+            state.eyelid,
+            state.confidence,
+            state.pupil,
+        ];
+        if values.iter().any(|v| !v.is_finite()) {
+            return (self.hold(), Some(Fault::NonFinite));
+        }
+
+        let held = self.hold();
+        let (eyelid, eyelid_fault) =
+            if (limits.min_eyelid..=limits.max_eyelid).contains(&state.eyelid) {
+                (state.eyelid, None)
+            } else {
+                (held.eyelid, Some(Fault::OutOfRange))
+            };
+
+        let gaze_fault = if state.valid {
+            self.check_gaze(&state, timestamp, limits).err()
+        } else {
+            None
+        };
+
+        if !state.valid || gaze_fault.is_some() {
+            return (
+                EyeGazeState {
+                    eyelid,
+                    pupil: state.pupil,
+                    ..held
+                },
+                gaze_fault.or(eyelid_fault),
+            );
+        }
+
+        let state = EyeGazeState { eyelid, ..state };
+        self.last_valid = Some((state, timestamp));
+        (state, eyelid_fault)
+    }
+
+    /// Last good state, marked invalid and without confidence.
+    pub fn hold(&self) -> EyeGazeState {
+        let state = self
+            .last_valid
+            .map(|(state, _)| state)
+            .unwrap_or_default();
+
+        EyeGazeState {
+            confidence: 0.0,
+            valid: false,
+            ..state
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.last_valid = None;
+    }
+
+    fn check_gaze(
+        &self,
+        state: &EyeGazeState,
+        timestamp: Timestamp,
+        limits: &OutputLimits,
+    ) -> Result<(), Fault> {
+        if state.pitch.abs() > limits.max_pitch || state.yaw.abs() > limits.max_yaw {
+            return Err(Fault::OutOfRange);
+        }
+
+        // The held state gets older with every rejection, so a real fast movement is
+        // accepted again after a few frames.
+        if let Some((last, last_timestamp)) = self.last_valid {
+            let elapsed = timestamp
+                .duration_since(last_timestamp)
+                .unwrap_or_default()
+                .as_secs_f32();
+            let distance = (state.pitch - last.pitch).hypot(state.yaw - last.yaw);
+            if elapsed > 0.0 && distance / elapsed > limits.max_angular_speed {
+                return Err(Fault::Jump);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::structs::ZERO_TIMESTAMP;
+
+    fn at(ms: u64) -> Timestamp {
+        ZERO_TIMESTAMP + Duration::from_millis(ms)
+    }
+
+    fn sample(yaw: f32, eyelid: f32) -> EyeGazeState {
+        EyeGazeState {
+            yaw,
+            eyelid,
+            confidence: 1.0,
+            valid: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn jump_holds_the_gaze_but_not_the_eyelid() {
+        let limits = OutputLimits::default();
+        let mut validator = OutputValidator::default();
+        validator.validate(sample(0.0, 0.8), at(0), &limits);
+
+        let (state, fault) = validator.validate(sample(40.0, 0.1), at(10), &limits);
+        assert_eq!(fault, Some(Fault::Jump));
+        assert_eq!(state.yaw, 0.0);
+        assert_eq!(state.eyelid, 0.1);
+        assert!(!state.valid);
+    }
+
+    #[test]
+    fn out_of_range_eyelid_keeps_the_gaze() {
+        let limits = OutputLimits::default();
+        let mut validator = OutputValidator::default();
+        validator.validate(sample(0.0, 0.8), at(0), &limits);
+
+        let (state, fault) = validator.validate(sample(1.0, 3.0), at(10), &limits);
+        assert_eq!(fault, Some(Fault::OutOfRange));
+        assert_eq!(state.yaw, 1.0);
+        assert_eq!(state.eyelid, 0.8);
+        assert!(state.valid);
+    }
+}
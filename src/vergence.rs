@@ -0,0 +1,129 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::structs::{DEFAULT_FOCUS_DISTANCE, DEFAULT_IPD, Eye, Timestamp};
+
+/// Smoothing starts over after a gap this long.
+const RESET_GAP: Duration = Duration::from_millis(500);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VergenceSettings {
+    /// Interpupillary distance, meters.
+    pub ipd: f32,
+    /// Focus distance is clamped to this range, meters. At the far limit the eyes are
+    /// nearly parallel, which also covers diverging eyes.
+    pub min_distance: f32,
+    pub max_distance: f32,
+    /// Time constant of the focus smoothing, seconds.
+    pub smoothing: f32,
+    /// The combined gaze starts from this eye instead of between both.
+    pub dominant_eye: Option<Eye>,
+    /// Keep the pitch of each eye instead of averaging them, e.g. for vertical strabismus.
+    pub independent_pitch: bool,
+    /// Largest pitch difference between the eyes in independent mode, degrees.
+    pub max_pitch_disparity: f32,
+}
+
+impl Default for VergenceSettings {
+    fn default() -> Self {
+        Self {
+            ipd: DEFAULT_IPD,
+            min_distance: 0.1,
+            max_distance: DEFAULT_FOCUS_DISTANCE,
+            smoothing: 0.15,
+            dominant_eye: None,
+            independent_pitch: false,
+            max_pitch_disparity: 5.0,
+        }
+    }
+}
+
+impl VergenceSettings {
+    /// Pitch of the left and right eye, around the average pitch of both.
+    pub fn eye_pitches(&self, l_pitch: f32, r_pitch: f32) -> (f32, f32) {
+        let avg_pitch = (l_pitch + r_pitch) / 2.0;
+        if !self.independent_pitch {
+            return (avg_pitch, avg_pitch);
+        }
+
+        let half_limit = self.max_pitch_disparity.max(0.0) / 2.0;
+        let half_disparity = ((l_pitch - r_pitch) / 2.0).clamp(-half_limit, half_limit);
+        (avg_pitch + half_disparity, avg_pitch - half_disparity)
+    }
+
+    /// Origin of the combined gaze ray relative to the point between the eyes, meters.
+    pub fn gaze_origin(&self) -> [f32; 3] {
+        match self.dominant_eye {
+            None => [0.0, 0.0, 0.0],
+            Some(Eye::L) => [-self.ipd / 2.0, 0.0, 0.0],
+            Some(Eye::R) => [self.ipd / 2.0, 0.0, 0.0],
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct VergenceControl {
+    settings: Mutex<VergenceSettings>,
+}
+
+impl VergenceControl {
+    pub fn settings(&self) -> VergenceSettings {
+        *self.settings.lock().unwrap()
+    }
+
+    pub fn set_settings(&self, settings: VergenceSettings) {
+        *self.settings.lock().unwrap() = settings;
+    }
+}
+
+/// Smooths the convergence of the eyes into a focus distance. Works in diopters, which are
+/// linear in the vergence angle, so the far range isn't dominated by tiny angle noise.
+/// The per eye yaws aren't touched, so they keep their own latency.
+#[derive(Debug, Default)]
+pub struct VergenceEstimator {
+    diopters: Option<f32>,
+    last_timestamp: Option<Timestamp>,
+}
+
+impl VergenceEstimator {
+    /// Focus distance in meters, `yaw_diff` is left yaw minus right yaw in degrees,
+    /// positive when converging.
+    pub fn update(
+        &mut self,
+        yaw_diff: f32,
+        timestamp: Timestamp,
+        settings: &VergenceSettings,
+    ) -> f32 {
+        let half_ipd = settings.ipd / 2.0;
+        let min_diopters = 1.0 / settings.max_distance.max(f32::EPSILON);
+        let max_diopters = (1.0 / settings.min_distance.max(f32::EPSILON)).max(min_diopters);
+
+        let measured = ((yaw_diff.to_radians() / 2.0).tan().max(0.0) / half_ipd)
+            .clamp(min_diopters, max_diopters);
+
+        let delta = self
+            .last_timestamp
+            .and_then(|last| timestamp.duration_since(last).ok())
+            .unwrap_or_default();
+        self.last_timestamp = Some(timestamp);
+
+        let diopters = match self.diopters {
+            Some(diopters) if delta <= RESET_GAP && settings.smoothing > 0.0 => {
+                let alpha = 1.0 - (-delta.as_secs_f32() / settings.smoothing).exp();
+                diopters + (measured - diopters) * alpha
+            }
+            _ => measured,
+        };
+        self.diopters = Some(diopters);
+
+        1.0 / diopters
+    }
+
+    /// Keeps the last distance, for when only one eye is tracked.
+    pub fn hold(&self, settings: &VergenceSettings) -> f32 {
+        let diopters = self
+            .diopters
+            .unwrap_or(1.0 / settings.max_distance.max(f32::EPSILON));
+        1.0 / diopters
+    }
+}
@@ -179,6 +179,7 @@ impl RenderContext {
         let io = imgui.context.io_mut();
 
         // Handle events from OpenXR inputs.
+        let mut special_gesture = false;
         unsafe {
             if let Some(inputs) = &LAYER.inputs {
                 for event in &inputs.events {
@@ -194,6 +195,8 @@ impl RenderContext {
                         UiEvent::PointerButton { down } => {
                             io.add_mouse_button_event(imgui::MouseButton::Left, *down);
                         }
+
+                        UiEvent::SpecialGesture => special_gesture = true,
                     }
                 }
             }
@@ -209,6 +212,9 @@ impl RenderContext {
 
         let renderer = &mut self.renderer;
         renderer.update(&mut self.renderer_ctx, &self.queue, &mut imgui.renderer);
+        if special_gesture {
+            renderer.special_gesture_triggered();
+        }
         unsafe {
             let openxr_layers = &mut LAYER.modules;
             renderer.render(ui, openxr_layers);